@@ -0,0 +1,156 @@
+//! App hostnames of the form `<uid>.<port>.<droid-server-id>.<domain>`, i.e. `7d6g824.7000.ds1.appoxy.com`.
+//!
+//! The global proxy rewrites `myapp.appoxy.com` into such a hostname, and the local proxy of the droid-server parses it
+//! back to reach the droid container `<uid>` on `<port>`. Everything producing or parsing these hostnames goes through
+//! `AppAddress` so that they all agree on the format.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Why an app address is invalid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    /// The uid is not a DNS label (1 to 63 lowercase letters, digits or hyphens, not starting or ending with a hyphen)
+    InvalidUid(String),
+    /// The port is not a number between 1 and 65535
+    InvalidPort(String),
+    /// The droid-server id is not a DNS label
+    InvalidServerId(String),
+    /// The domain is not a dot-separated list of DNS labels
+    InvalidDomain(String),
+    /// The hostname does not have the `<uid>.<port>.<droid-server-id>.<domain>` shape
+    Malformed(String),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidUid(uid) => write!(f, "Invalid uid '{}'", uid),
+            AddressError::InvalidPort(port) => write!(f, "Invalid port '{}'", port),
+            AddressError::InvalidServerId(id) => write!(f, "Invalid droid-server id '{}'", id),
+            AddressError::InvalidDomain(domain) => write!(f, "Invalid domain '{}'", domain),
+            AddressError::Malformed(host) => write!(f, "'{}' is not a <uid>.<port>.<droid-server-id>.<domain> hostname", host),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// Whether `label` can be used as is in a hostname: 1 to 63 lowercase letters, digits or hyphens,
+/// not starting or ending with a hyphen.
+pub fn is_dns_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// Fails if `uid` cannot name a droid container and be the first label of its hostname
+pub fn validate_uid(uid: &str) -> Result<(), AddressError> {
+    match is_dns_label(uid) {
+        true => Ok(()),
+        false => Err(AddressError::InvalidUid(uid.to_string())),
+    }
+}
+
+/// Where an app is served: the droid `uid` listening on `port`, on the droid-server `server_id`, under `domain`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AppAddress {
+    pub uid: String,
+    pub port: u16,
+    pub server_id: String,
+    pub domain: String,
+}
+
+impl AppAddress {
+    /// Validates and builds an address. The server id and the domain are lowercased, hostnames being case-insensitive.
+    pub fn new(uid: &str, port: u16, server_id: &str, domain: &str) -> Result<AppAddress, AddressError> {
+        validate_uid(uid)?;
+        if port == 0 {
+            return Err(AddressError::InvalidPort(port.to_string()));
+        }
+        let server_id = server_id.to_lowercase();
+        if !is_dns_label(&server_id) {
+            return Err(AddressError::InvalidServerId(server_id));
+        }
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        if !domain.split('.').all(is_dns_label) {
+            return Err(AddressError::InvalidDomain(domain));
+        }
+        Ok(AppAddress { uid: uid.to_string(), port, server_id, domain })
+    }
+
+    /// The address without its domain, `<uid>.<port>.<droid-server-id>`
+    pub fn route(&self) -> String {
+        format!("{}.{}.{}", self.uid, self.port, self.server_id)
+    }
+
+    /// Where the droid is reached on the droid network, `<uid>:<port>`
+    pub fn upstream(&self) -> String {
+        format!("{}:{}", self.uid, self.port)
+    }
+}
+
+impl fmt::Display for AppAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.route(), self.domain)
+    }
+}
+
+impl FromStr for AppAddress {
+    type Err = AddressError;
+
+    /// Parses a hostname, as found in a `Host` header: case-insensitive, with an optional `:<port>` suffix.
+    fn from_str(host: &str) -> Result<AppAddress, AddressError> {
+        let host = host.trim();
+        let hostname = host.rsplit_once(':').map_or(host, |(hostname, _)| hostname).to_lowercase();
+        let mut labels = hostname.splitn(4, '.');
+        let (uid, port, server_id, domain) = match (labels.next(), labels.next(), labels.next(), labels.next()) {
+            (Some(uid), Some(port), Some(server_id), Some(domain)) => (uid, port, server_id, domain),
+            _ => return Err(AddressError::Malformed(host.to_string())),
+        };
+        let port = match port.parse() {
+            Ok(port) => port,
+            Err(_) => return Err(AddressError::InvalidPort(port.to_string())),
+        };
+        AppAddress::new(uid, port, server_id, domain)
+    }
+}
+
+#[test]
+fn test_format() {
+    println!("An address should format as <uid>.<port>.<droid-server-id>.<domain>");
+    let address = AppAddress::new("7d6g824", 7000, "ds1", "appoxy.com").unwrap();
+    assert_eq!(address.to_string(), "7d6g824.7000.ds1.appoxy.com");
+    assert_eq!(address.route(), "7d6g824.7000.ds1");
+    assert_eq!(address.upstream(), "7d6g824:7000");
+}
+
+#[test]
+fn test_parse() {
+    let address: AppAddress = "7d6g824.7000.ds1.appoxy.com".parse().unwrap();
+    assert_eq!(address, AppAddress::new("7d6g824", 7000, "ds1", "appoxy.com").unwrap());
+
+    println!("Hostnames are case-insensitive and Host headers may carry a port");
+    let address: AppAddress = "7D6G824.7000.DS1.Appoxy.com:8080".parse().unwrap();
+    assert_eq!(address.to_string(), "7d6g824.7000.ds1.appoxy.com");
+
+    println!("Formatting and parsing should round-trip");
+    assert_eq!(address.to_string().parse::<AppAddress>().unwrap(), address);
+}
+
+#[test]
+fn test_invalid() {
+    println!("Hostnames that are not app addresses, or with labels that are not DNS-safe, should be rejected");
+    assert_eq!("myapp.appoxy.com".parse::<AppAddress>(), Err(AddressError::Malformed("myapp.appoxy.com".to_string())));
+    assert_eq!("myapp.www.appoxy.com".parse::<AppAddress>(), Err(AddressError::InvalidPort("www".to_string())));
+    assert_eq!("localhost".parse::<AppAddress>(), Err(AddressError::Malformed("localhost".to_string())));
+    assert_eq!("7d6g824.70000.ds1.appoxy.com".parse::<AppAddress>(), Err(AddressError::InvalidPort("70000".to_string())));
+    assert_eq!("7d6g824.0.ds1.appoxy.com".parse::<AppAddress>(), Err(AddressError::InvalidPort("0".to_string())));
+    assert_eq!("-app.7000.ds1.appoxy.com".parse::<AppAddress>(), Err(AddressError::InvalidUid("-app".to_string())));
+    assert_eq!("7d6g824.7000.ds_1.appoxy.com".parse::<AppAddress>(), Err(AddressError::InvalidServerId("ds_1".to_string())));
+    assert_eq!("7d6g824.7000.ds1.appoxy..com".parse::<AppAddress>(), Err(AddressError::InvalidDomain("appoxy..com".to_string())));
+    assert_eq!(AppAddress::new("My_App", 7000, "ds1", "appoxy.com"), Err(AddressError::InvalidUid("My_App".to_string())));
+    assert_eq!(AppAddress::new(&"a".repeat(64), 7000, "ds1", "appoxy.com"), Err(AddressError::InvalidUid("a".repeat(64))));
+}
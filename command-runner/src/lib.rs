@@ -0,0 +1,106 @@
+//! External commands (pack, git, nginx, ...) of the DSI and the global router. They are run through a
+//! `CommandRunner`, so that tests can replace them with a scripted fake.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub mod nginx;
+
+/// Output stream of a spawned command
+pub type Output = Box<dyn AsyncRead + Send + Unpin>;
+
+/// An external command (pack, git, ...) to be run by a `CommandRunner`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub dir: Option<String>,
+}
+
+impl CommandSpec {
+    pub fn new(program: &str) -> CommandSpec {
+        CommandSpec { program: program.to_string(), ..Default::default() }
+    }
+
+    pub fn arg<S: ToString>(mut self, arg: S) -> CommandSpec {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Sets an environment variable of the command. Unlike the arguments, it is left out of `line`, and so of the logs.
+    pub fn env(mut self, key: &str, value: &str) -> CommandSpec {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Runs the command in `dir`
+    pub fn dir(mut self, dir: &str) -> CommandSpec {
+        self.dir = Some(dir.to_string());
+        self
+    }
+
+    /// The command line, as it would be typed in a shell
+    pub fn line(&self) -> String {
+        std::iter::once(self.program.as_str()).chain(self.args.iter().map(|a| a.as_str())).collect::<Vec<&str>>().join(" ")
+    }
+}
+
+/// A spawned command. `exit` resolves to the exit code once the process is done.
+pub struct Process {
+    pub pid: Option<u32>,
+    pub stdout: Output,
+    pub stderr: Output,
+    pub exit: Pin<Box<dyn Future<Output = io::Result<i32>> + Send>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs the external commands, so that they can be replaced by a scripted fake in tests.
+pub trait CommandRunner: Send + Sync {
+    fn spawn(&self, command: &CommandSpec) -> io::Result<Process>;
+}
+
+/// Runs the command to completion and collects its output
+pub async fn output(runner: &dyn CommandRunner, command: &CommandSpec) -> io::Result<CommandOutput> {
+    let mut process = runner.spawn(command)?;
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let (out, err) = tokio::join!(process.stdout.read_to_string(&mut stdout), process.stderr.read_to_string(&mut stderr));
+    out?;
+    err?;
+    Ok(CommandOutput { code: process.exit.await?, stdout, stderr })
+}
+
+/// Runs commands as tokio child processes
+pub struct TokioRunner;
+
+impl CommandRunner for TokioRunner {
+    fn spawn(&self, command: &CommandSpec) -> io::Result<Process> {
+        let mut cmd = tokio::process::Command::new(&command.program);
+        cmd.args(&command.args)
+            .envs(command.env.iter().cloned())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        if let Some(dir) = &command.dir {
+            cmd.current_dir(dir);
+        }
+
+        let mut child = cmd.spawn()?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("Could not capture standard output."))?;
+        let stderr = child.stderr.take().ok_or_else(|| io::Error::other("Could not capture standard error."))?;
+        Ok(Process {
+            pid: child.id(),
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            exit: Box::pin(async move { child.wait().await.map(|status| status.code().unwrap_or(-1)) }),
+        })
+    }
+}
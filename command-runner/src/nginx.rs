@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use crate::{output, CommandRunner, CommandSpec};
+
+/// A config file included by nginx. It is only ever replaced through `sync`, which validates it with `nginx -t`
+/// and reloads nginx.
+#[derive(Clone)]
+pub struct NginxConfig {
+    /// File included by nginx
+    pub path: String,
+    /// nginx binary
+    pub nginx: String,
+    pub runner: Arc<dyn CommandRunner>,
+    /// Serializes the syncs, so that two of them never write or reload at the same time
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// Writes `contents` to `path` through a temporary file, so that nginx never reads a half-written config
+fn write_atomic(path: &str, contents: &str) -> std::io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+impl NginxConfig {
+    pub fn new(path: &str, nginx: &str, runner: Arc<dyn CommandRunner>) -> NginxConfig {
+        NginxConfig { path: path.to_string(), nginx: nginx.to_string(), runner, lock: Arc::new(tokio::sync::Mutex::new(())) }
+    }
+
+    /// Runs `nginx <args>`, failing with its stderr if it exits with a non-zero code
+    async fn run(&self, args: &[&str]) -> Result<(), String> {
+        let command = args.iter().fold(CommandSpec::new(&self.nginx), |command, arg| command.arg(arg));
+        let output = output(self.runner.as_ref(), &command).await
+            .map_err(|e| format!("Error running {}: {}", command.line(), e))?;
+        match output.code {
+            0 => Ok(()),
+            code => Err(format!("{} exited with code {}: {}", command.line(), code, output.stderr.trim())),
+        }
+    }
+
+    /// Writes the config returned by `render` and, if it changed, validates it with `nginx -t` and reloads nginx.
+    /// An invalid config is rolled back so that nginx keeps serving the previous one. `render` is called once the
+    /// previous sync is done, so that the last one always writes the latest state.
+    pub async fn sync(&self, render: impl FnOnce() -> String) -> Result<(), String> {
+        let _guard = self.lock.lock().await;
+        let config = render();
+        let previous = std::fs::read_to_string(&self.path).ok();
+        if previous.as_deref() == Some(config.as_str()) {
+            return Ok(());
+        }
+
+        write_atomic(&self.path, &config).map_err(|e| format!("Error writing {}: {}", self.path, e))?;
+        if let Err(err) = self.run(&["-t"]).await {
+            let rollback = match &previous {
+                Some(previous) => write_atomic(&self.path, previous),
+                None => std::fs::remove_file(&self.path),
+            };
+            if let Err(e) = rollback {
+                println!("Error rolling back {}: {}", self.path, e);
+            }
+            return Err(err);
+        }
+        self.run(&["-s", "reload"]).await
+    }
+}
@@ -0,0 +1,18 @@
+use rocket::serde::{Deserialize, Serialize};
+
+/// DAMS settings, extracted from Rocket's figment (Rocket.toml or `ROCKET_*` environment variables).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct DamsConfig {
+    /// Seconds without a heartbeat after which a droid-server is no longer considered for placements
+    pub heartbeat_timeout: u64,
+}
+
+impl Default for DamsConfig {
+    fn default() -> Self {
+        DamsConfig {
+            heartbeat_timeout: 30,
+        }
+    }
+}
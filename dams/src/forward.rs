@@ -0,0 +1,19 @@
+use rocket::serde::json::Value;
+use crate::registry::Server;
+
+/// Sends the droid spec to the `/droids` endpoint of the server's DSI. Only the response status is awaited:
+/// the DSI keeps building after the build event stream is dropped, and clients follow the build at
+/// `/droids/<app_id>/build` on the server.
+pub async fn forward(server: &Server, droid: &Value) -> Result<(), String> {
+    let url = format!("{}/droids", server.registration.url.trim_end_matches('/'));
+    let response = reqwest::Client::new()
+        .post(&url)
+        .header("Content-Type", "application/json")
+        .body(droid.to_string())
+        .send().await
+        .map_err(|e| format!("Error sending the droid to {}: {}", url, e))?;
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(format!("{} rejected the droid ({})", url, response.status())),
+    }
+}
@@ -0,0 +1,86 @@
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{serde_json, Value};
+use rocket::serde::json::serde_json::json;
+use crate::test_support::FakeDsi;
+
+fn droid(app_id: i64, stack: &str) -> String {
+    json!({
+        "app_id": app_id,
+        "repo": "https://github.com/heroku/node-js-getting-started",
+        "branch": "main",
+        "buildpacks": [],
+        "env": ["PORT=8080"],
+        "stack": {"id": stack, "build_image": "", "run_image": ""}
+    }).to_string()
+}
+
+#[rocket::async_test]
+async fn droid_placement() {
+    println!("New droids should be forwarded to the registered server with the most free resources");
+
+    let client = Client::tracked(crate::dams(rocket::build())).await.expect("valid rocket instance");
+    let jammy = "io.buildpacks.stacks.jammy";
+    let busy = FakeDsi::start("ds1", 200).await;
+    let free = FakeDsi::start("ds2", 200).await;
+    let bionic = FakeDsi::start("ds3", 200).await;
+    busy.register(&client, vec![], 100, 0.9, 12).await;
+    free.register(&client, vec![jammy], 900, 0.1, 2).await;
+    bionic.register(&client, vec!["io.buildpacks.stacks.bionic"], 1000, 0.0, 0).await;
+
+    let response = client.get(format!("/placements?stack={}", jammy)).dispatch().await;
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["server_id"], "ds2");
+
+    let response = client.post("/droids").body(droid(1, jammy)).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["server_id"], "ds2");
+    assert_eq!(body["data"]["build_events"], format!("{}/droids/1/build", free.url));
+    assert_eq!(free.received().len(), 1);
+    assert_eq!(free.received()[0]["app_id"], 1);
+    assert!(busy.received().is_empty() && bionic.received().is_empty());
+
+    let response = client.get("/servers/ds1").dispatch().await;
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["alive"], true);
+    assert_eq!(body["data"]["capacity"]["droids"], 12);
+
+    println!("Deregistered servers should not be placed on");
+    assert_eq!(client.delete("/servers/ds2").dispatch().await.status(), Status::Ok);
+    let response = client.post("/droids").body(droid(2, jammy)).dispatch().await;
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["server_id"], "ds1");
+    assert_eq!(busy.received()[0]["app_id"], 2);
+
+    println!("Heartbeats of unknown servers should be refused so that they register again");
+    let response = client.put("/servers/ds2/heartbeat")
+        .body(r#"{"capacity": {"memory_total": 1024, "memory_free": 900, "cpus": 4, "cpu_usage": 0.1, "droids": 2}}"#)
+        .dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn droid_placement_fallback() {
+    println!("A droid rejected by the best server should be forwarded to the next one");
+
+    let client = Client::tracked(crate::dams(rocket::build())).await.expect("valid rocket instance");
+    let failing = FakeDsi::start("ds1", 500).await;
+    let fallback = FakeDsi::start("ds2", 200).await;
+    failing.register(&client, vec![], 1000, 0.0, 0).await;
+    fallback.register(&client, vec![], 200, 0.5, 4).await;
+
+    let response = client.post("/droids").body(droid(3, "io.buildpacks.stacks.jammy")).dispatch().await;
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["server_id"], "ds2");
+    assert_eq!(failing.received().len(), 1);
+    assert_eq!(fallback.received().len(), 1);
+
+    println!("Without any server accepting the droid, the placement should fail");
+    assert_eq!(client.delete("/servers/ds2").dispatch().await.status(), Status::Ok);
+    let response = client.post("/droids").body(droid(4, "io.buildpacks.stacks.jammy")).dispatch().await;
+    assert_eq!(response.status(), Status::BadGateway);
+    assert_eq!(client.delete("/servers/ds1").dispatch().await.status(), Status::Ok);
+    let response = client.post("/droids").body(droid(4, "io.buildpacks.stacks.jammy")).dispatch().await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+}
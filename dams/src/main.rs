@@ -0,0 +1,31 @@
+#[macro_use] extern crate rocket;
+use rocket::{Build, Rocket};
+use rocket::fairing::AdHoc;
+use crate::registry::Registry;
+
+#[cfg(test)] mod integration_tests;
+#[cfg(test)] mod test_support;
+mod config;
+mod forward;
+mod placement;
+mod registry;
+mod routes;
+
+/// Attaches the droid-server registry and the routes to `rocket`.
+fn dams(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket
+        .attach(AdHoc::config::<config::DamsConfig>())
+        .manage(Registry::default())
+        .mount("/servers", routes![routes::register, routes::heartbeat, routes::deregister, routes::get, routes::list])
+        .mount("/placements", routes![routes::placement])
+        .mount("/droids", routes![routes::deploy])
+}
+
+#[rocket::main]
+async fn main() -> Result<(), Box<rocket::Error>> {
+    let _rocket = dams(rocket::build())
+        .launch()
+        .await?;
+
+    Ok(())
+}
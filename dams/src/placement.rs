@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+use crate::registry::Server;
+
+/// How much a server is preferred for a new droid: mostly its free memory, then its idle cpu and how few
+/// droids it already runs. Between 0.0 and 1.0.
+pub fn score(server: &Server) -> f64 {
+    let capacity = match &server.capacity {
+        Some(capacity) => capacity,
+        None => return 0.0
+    };
+    let free_memory = match capacity.memory_total {
+        0 => 0.0,
+        total => capacity.memory_free.min(total) as f64 / total as f64,
+    };
+    let idle_cpu = 1.0 - capacity.cpu_usage.clamp(0.0, 1.0);
+    let density = 1.0 / (1.0 + capacity.droids as f64);
+    0.5 * free_memory + 0.3 * idle_cpu + 0.2 * density
+}
+
+/// The servers a droid on `stack` can be placed on, best first. Servers that did not send a heartbeat within
+/// `timeout`, have no free memory, or do not support the stack are left out.
+pub fn candidates(servers: Vec<Server>, stack: Option<&str>, now: Instant, timeout: Duration) -> Vec<Server> {
+    let mut candidates: Vec<(f64, Server)> = servers.into_iter()
+        .filter(|server| matches!(server.last_seen, Some(seen) if now.saturating_duration_since(seen) <= timeout))
+        .filter(|server| matches!(&server.capacity, Some(capacity) if capacity.memory_free > 0))
+        .filter(|server| {
+            let stacks = &server.registration.capabilities.stacks;
+            stacks.is_empty() || stack.is_none_or(|stack| stacks.iter().any(|s| s == stack))
+        })
+        .map(|server| (score(&server), server))
+        .collect();
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.registration.server_id.cmp(&b.1.registration.server_id)));
+    candidates.into_iter().map(|(_, server)| server).collect()
+}
+
+#[test]
+fn test_candidates() {
+    use crate::registry::{Capabilities, Capacity, Registration};
+
+    let now = Instant::now();
+    let server = |id: &str, memory_free: u64, cpu_usage: f64, droids: usize, stacks: Vec<&str>, seen: Option<Instant>| Server {
+        registration: Registration {
+            server_id: id.to_string(),
+            url: format!("http://{}", id),
+            domains: vec![],
+            version: "0.1.0".to_string(),
+            capabilities: Capabilities { stacks: stacks.into_iter().map(String::from).collect(), builder_cache: false },
+        },
+        capacity: Some(Capacity { memory_total: 100, memory_free, cpus: 4, cpu_usage, droids }),
+        droids: vec![],
+        last_seen: seen,
+    };
+    let timeout = Duration::from_secs(30);
+
+    println!("Servers should be ranked by free memory, cpu and droid count");
+    let servers = vec![
+        server("busy", 20, 0.9, 10, vec![], Some(now)),
+        server("free", 80, 0.1, 1, vec![], Some(now)),
+        server("crowded", 80, 0.1, 20, vec![], Some(now)),
+    ];
+    let ids: Vec<String> = candidates(servers, None, now, timeout).into_iter().map(|s| s.registration.server_id).collect();
+    assert_eq!(ids, vec!["free", "crowded", "busy"]);
+
+    println!("Stale, full and incompatible servers should not be candidates");
+    let servers = vec![
+        server("stale", 80, 0.1, 1, vec![], Some(now - Duration::from_secs(60))),
+        server("unseen", 80, 0.1, 1, vec![], None),
+        server("full", 0, 0.1, 1, vec![], Some(now)),
+        server("jammy", 50, 0.5, 5, vec!["io.buildpacks.stacks.jammy"], Some(now)),
+        server("bionic", 80, 0.1, 1, vec!["io.buildpacks.stacks.bionic"], Some(now)),
+    ];
+    let ids: Vec<String> = candidates(servers, Some("io.buildpacks.stacks.jammy"), now, timeout).into_iter().map(|s| s.registration.server_id).collect();
+    assert_eq!(ids, vec!["jammy"]);
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use rocket::serde::{Deserialize, Serialize};
+
+/// What a droid-server can build and run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Capabilities {
+    /// Stack ids the server has builders for, any stack is accepted if empty
+    pub stacks: Vec<String>,
+    /// Whether the server keeps a builder cache between builds
+    pub builder_cache: bool,
+}
+
+/// Sent by a DSI when it starts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Registration {
+    pub server_id: String,
+    /// Base url of the DSI, i.e. "http://10.0.0.4:8000"
+    pub url: String,
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+}
+
+/// Free resources of a droid-server
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Capacity {
+    /// Bytes
+    pub memory_total: u64,
+    /// Bytes
+    pub memory_free: u64,
+    pub cpus: u32,
+    /// Fraction of the cpus in use, from 0.0 to 1.0
+    pub cpu_usage: f64,
+    /// Droids deployed on the server
+    pub droids: usize,
+}
+
+/// State of one of the droids of a server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DroidReport {
+    pub app_id: i64,
+    pub uid: String,
+    pub status: String,
+}
+
+/// Sent periodically by a registered DSI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Heartbeat {
+    pub capacity: Capacity,
+    #[serde(default)]
+    pub droids: Vec<DroidReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Server {
+    #[serde(flatten)]
+    pub registration: Registration,
+    /// Last reported capacity, None until the first heartbeat
+    pub capacity: Option<Capacity>,
+    pub droids: Vec<DroidReport>,
+    #[serde(skip)]
+    pub last_seen: Option<Instant>,
+}
+
+/// The droid-servers known to DAMS. Kept in memory only: DSIs register again when they restart, and when a
+/// heartbeat is refused because DAMS restarted.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    servers: Arc<Mutex<BTreeMap<String, Server>>>,
+}
+
+impl Registry {
+    /// Adds the server, or replaces it if it registers again. It is not placed on until its first heartbeat.
+    pub fn register(&self, registration: Registration) {
+        let server = Server { registration, capacity: None, droids: vec![], last_seen: None };
+        self.servers.lock().unwrap().insert(server.registration.server_id.clone(), server);
+    }
+
+    /// Records the heartbeat of a server, false if the server is not registered
+    pub fn heartbeat(&self, server_id: &str, heartbeat: Heartbeat) -> bool {
+        match self.servers.lock().unwrap().get_mut(server_id) {
+            Some(server) => {
+                server.capacity = Some(heartbeat.capacity);
+                server.droids = heartbeat.droids;
+                server.last_seen = Some(Instant::now());
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn deregister(&self, server_id: &str) -> bool {
+        self.servers.lock().unwrap().remove(server_id).is_some()
+    }
+
+    pub fn get(&self, server_id: &str) -> Option<Server> {
+        self.servers.lock().unwrap().get(server_id).cloned()
+    }
+
+    pub fn all(&self) -> Vec<Server> {
+        self.servers.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[test]
+fn test_registry() {
+    println!("Heartbeats should only be accepted from registered servers");
+    let registry = Registry::default();
+    let heartbeat = Heartbeat { capacity: Capacity { memory_total: 8, memory_free: 4, cpus: 2, cpu_usage: 0.5, droids: 1 }, droids: vec![] };
+    assert!(!registry.heartbeat("ds1", heartbeat.clone()));
+
+    registry.register(Registration {
+        server_id: "ds1".to_string(),
+        url: "http://127.0.0.1:8000".to_string(),
+        domains: vec![],
+        version: "0.1.0".to_string(),
+        capabilities: Capabilities::default(),
+    });
+    assert!(registry.get("ds1").unwrap().last_seen.is_none());
+    assert!(registry.heartbeat("ds1", heartbeat.clone()));
+    assert_eq!(registry.get("ds1").unwrap().capacity, Some(heartbeat.capacity));
+
+    assert!(registry.deregister("ds1"));
+    assert!(registry.all().is_empty());
+}
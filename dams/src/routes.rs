@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::json::serde_json::json;
+use rocket::State;
+use crate::config::DamsConfig;
+use crate::forward::forward;
+use crate::placement::candidates;
+use crate::registry::{Heartbeat, Registration, Registry, Server};
+
+fn not_registered(server_id: &str) -> status::Custom<Value> {
+    status::Custom(Status::NotFound, json!({
+        "message": "Server not registered",
+        "data": {
+            "server_id": server_id
+        }
+    }))
+}
+
+/// Registers a DSI, or updates its registration when it restarts
+#[post("/", data = "<registration>")]
+pub fn register(registration: Json<Registration>, registry: &State<Registry>) -> Result<Value, status::Custom<Value>> {
+    if registration.server_id.is_empty() || registration.url.is_empty() {
+        return Err(status::Custom(Status::BadRequest, json!({
+            "message": "A server id and url are required",
+            "data": {}
+        })));
+    }
+    let server_id = registration.server_id.clone();
+    println!("Droid-server {} registered at {}", server_id, registration.url);
+    registry.register(registration.into_inner());
+    Ok(json!({
+        "message": "Server registered",
+        "data": {
+            "server_id": server_id
+        }
+    }))
+}
+
+/// Records the capacity and droids of a DSI. Unknown servers get a 404 and are expected to register again.
+#[put("/<server_id>/heartbeat", data = "<heartbeat>")]
+pub fn heartbeat(server_id: &str, heartbeat: Json<Heartbeat>, registry: &State<Registry>) -> Result<Value, status::Custom<Value>> {
+    match registry.heartbeat(server_id, heartbeat.into_inner()) {
+        true => Ok(json!({
+            "message": "Heartbeat recorded",
+            "data": {}
+        })),
+        false => Err(not_registered(server_id))
+    }
+}
+
+#[delete("/<server_id>")]
+pub fn deregister(server_id: &str, registry: &State<Registry>) -> Result<Value, status::Custom<Value>> {
+    match registry.deregister(server_id) {
+        true => {
+            println!("Droid-server {} deregistered", server_id);
+            Ok(json!({
+                "message": "Server deregistered",
+                "data": {}
+            }))
+        }
+        false => Err(not_registered(server_id))
+    }
+}
+
+#[get("/<server_id>")]
+pub fn get(server_id: &str, registry: &State<Registry>, config: &State<DamsConfig>) -> Result<Value, status::Custom<Value>> {
+    match registry.get(server_id) {
+        Some(server) => Ok(json!({
+            "message": "Droid-server",
+            "data": describe(server, Duration::from_secs(config.heartbeat_timeout))
+        })),
+        None => Err(not_registered(server_id))
+    }
+}
+
+/// The server with whether it is alive, and the seconds since its last heartbeat
+fn describe(server: Server, timeout: Duration) -> Value {
+    let since = server.last_seen.map(|seen| seen.elapsed());
+    let mut value = json!(server);
+    value["alive"] = json!(matches!(since, Some(since) if since <= timeout));
+    value["last_heartbeat_secs"] = json!(since.map(|since| since.as_secs()));
+    value
+}
+
+#[get("/")]
+pub fn list(registry: &State<Registry>, config: &State<DamsConfig>) -> Value {
+    let timeout = Duration::from_secs(config.heartbeat_timeout);
+    let servers: Vec<Value> = registry.all().into_iter().map(|server| describe(server, timeout)).collect();
+    json!({
+        "message": "Droid-servers",
+        "data": {
+            "servers": servers
+        }
+    })
+}
+
+/// The server a new droid on `stack` would be placed on, without deploying anything
+#[get("/?<stack>")]
+pub fn placement(stack: Option<&str>, registry: &State<Registry>, config: &State<DamsConfig>) -> status::Custom<Value> {
+    let timeout = Duration::from_secs(config.heartbeat_timeout);
+    match candidates(registry.all(), stack, Instant::now(), timeout).into_iter().next() {
+        Some(server) => status::Custom(Status::Ok, json!({
+            "message": "Placement",
+            "data": {
+                "server_id": server.registration.server_id,
+                "url": server.registration.url
+            }
+        })),
+        None => no_server()
+    }
+}
+
+fn no_server() -> status::Custom<Value> {
+    status::Custom(Status::ServiceUnavailable, json!({
+        "message": "No droid-server available",
+        "data": {}
+    }))
+}
+
+/// Places a new droid and forwards its spec to the DSI of the chosen server. If that DSI rejects it or is
+/// unreachable, the next best server is tried.
+#[post("/", data = "<droid>")]
+pub async fn deploy(droid: Json<Value>, registry: &State<Registry>, config: &State<DamsConfig>) -> status::Custom<Value> {
+    let app_id = match droid["app_id"].as_i64() {
+        Some(app_id) => app_id,
+        None => return status::Custom(Status::BadRequest, json!({
+            "message": "The droid has no app_id",
+            "data": {}
+        }))
+    };
+    let timeout = Duration::from_secs(config.heartbeat_timeout);
+    let stack = droid["stack"]["id"].as_str();
+    let mut errors = vec![];
+    for server in candidates(registry.all(), stack, Instant::now(), timeout) {
+        match forward(&server, &droid).await {
+            Ok(()) => {
+                println!("Droid {} placed on {}", app_id, server.registration.server_id);
+                return status::Custom(Status::Ok, json!({
+                    "message": "Droid placed",
+                    "data": {
+                        "server_id": server.registration.server_id,
+                        "url": server.registration.url,
+                        "build_events": format!("{}/droids/{}/build", server.registration.url.trim_end_matches('/'), app_id)
+                    }
+                }));
+            }
+            Err(err) => {
+                println!("{}", err);
+                errors.push(err);
+            }
+        }
+    }
+    match errors.is_empty() {
+        true => no_server(),
+        false => status::Custom(Status::BadGateway, json!({
+            "message": "No droid-server accepted the droid",
+            "data": {
+                "errors": errors
+            }
+        }))
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::{Arc, Mutex};
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{serde_json, Value};
+use rocket::serde::json::serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// An in-process fake DSI: answers `POST /droids` with `status` and records the droid specs it received.
+pub struct FakeDsi {
+    pub server_id: String,
+    pub url: String,
+    pub droids: Arc<Mutex<Vec<Value>>>,
+}
+
+/// Reads a request and returns its body, using its Content-Length
+async fn read_body(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut request = Vec::new();
+    let mut buf = vec![0; 8192];
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text[..end].lines()
+                .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|l| l.trim().to_string()))
+                .and_then(|length| length.parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                return Ok(String::from_utf8_lossy(&request[end + 4..end + 4 + length]).to_string());
+            }
+        }
+    }
+    Ok(String::new())
+}
+
+impl FakeDsi {
+    pub async fn start(server_id: &str, status: u16) -> FakeDsi {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let droids = Arc::new(Mutex::new(Vec::new()));
+        let received = droids.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let body = read_body(&mut stream).await.unwrap_or_default();
+                    if let Ok(droid) = serde_json::from_str(&body) {
+                        received.lock().unwrap().push(droid);
+                    }
+                    let response = format!("HTTP/1.1 {} Fake\r\nContent-Type: text/event-stream\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        FakeDsi { server_id: server_id.to_string(), url, droids }
+    }
+
+    /// Registers the fake DSI and reports its capacity, as a real DSI does when it starts
+    pub async fn register(&self, client: &Client, stacks: Vec<&str>, memory_free: u64, cpu_usage: f64, droids: usize) {
+        let response = client.post("/servers")
+            .body(json!({
+                "server_id": self.server_id,
+                "url": self.url,
+                "domains": ["localhost"],
+                "version": "0.1.0",
+                "capabilities": {"stacks": stacks, "builder_cache": true}
+            }).to_string())
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.put(format!("/servers/{}/heartbeat", self.server_id))
+            .body(json!({
+                "capacity": {"memory_total": 1024, "memory_free": memory_free, "cpus": 4, "cpu_usage": cpu_usage, "droids": droids},
+                "droids": []
+            }).to_string())
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    pub fn received(&self) -> Vec<Value> {
+        self.droids.lock().unwrap().clone()
+    }
+}
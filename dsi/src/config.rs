@@ -0,0 +1,86 @@
+use rocket::serde::{Deserialize, Serialize};
+use crate::models::resources::Resources;
+
+/// DSI settings, extracted from Rocket's figment (Rocket.toml or `ROCKET_*` environment variables).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct DsiConfig {
+    /// Directory where builder configs and droid states are dumped, one subdirectory per app
+    pub dumps_dir: String,
+    /// Seconds between two reconciliation runs. 0 only reconciles on boot.
+    pub reconcile_interval: u64,
+    /// If false, orphaned containers, images and dumps are only reported by the reconciler
+    pub remove_orphans: bool,
+    /// Unix socket of the Docker Engine API
+    pub docker_socket: String,
+    /// Docker network the droid containers are attached to, shared with the local nginx proxy
+    pub network: String,
+    /// Buildpack registry API, buildpack infos are fetched from `<registry_url>/<namespace>/<name>`
+    pub registry_url: String,
+    /// nginx config file rendered from the droid states, included by the local nginx proxy
+    pub nginx_config: String,
+    /// DNS server nginx uses to resolve the droid containers, the embedded DNS of docker by default
+    pub nginx_resolver: String,
+    /// Id of this droid-server, droids are reachable at `<uid>.<port>.<server_id>.<domain>`
+    pub server_id: String,
+    /// Domains the droids are served on
+    pub domains: Vec<String>,
+    /// Address the built-in local proxy listens on, i.e. "0.0.0.0:80". Unset, the proxy is not started.
+    pub proxy_address: Option<String>,
+    /// Seconds a request to a snoozed droid is held by the local proxy while the droid wakes up
+    pub wake_timeout: u64,
+    /// Global router the named droids are reported to, i.e. "http://router.appoxy.com". Unset, nothing is reported.
+    pub router_url: Option<String>,
+    /// DAMS this server registers with and sends heartbeats to, i.e. "http://dams.appoxy.com". Unset, the server
+    /// is not announced.
+    pub dams_url: Option<String>,
+    /// Url DAMS reaches this DSI at. Unset, `http://<address>:<port>` of the Rocket config.
+    pub advertise_url: Option<String>,
+    /// Seconds between two heartbeats to DAMS
+    pub heartbeat_interval: u64,
+    /// Stack ids this server builds droids on, announced to DAMS. Empty means any stack.
+    pub stacks: Vec<String>,
+    /// Whether `pack` keeps its build cache between the builds of a droid, announced to DAMS
+    pub builder_cache: bool,
+    /// Resource limits of the droids that do not request them
+    pub default_resources: Resources,
+    /// Highest resource limits a droid may request, droids requesting more are rejected
+    pub max_resources: Resources,
+    /// PORT given to the droids that do not set it
+    pub default_port: u16,
+    /// File holding the base64 encoded key the secrets of the droids are encrypted with, generated if missing
+    pub secret_key_file: String,
+    /// Largest source archive that may be uploaded to build a droid, in MiB
+    pub max_upload_size: u64,
+}
+
+impl Default for DsiConfig {
+    fn default() -> Self {
+        DsiConfig {
+            dumps_dir: "./dumps".to_string(),
+            reconcile_interval: 60,
+            remove_orphans: false,
+            docker_socket: "/var/run/docker.sock".to_string(),
+            network: "droid-net".to_string(),
+            registry_url: "https://cnb-registry-api-staging.herokuapp.com/api/v1/buildpacks".to_string(),
+            nginx_config: "/etc/nginx/sites-available/droid-server".to_string(),
+            nginx_resolver: "127.0.0.11".to_string(),
+            server_id: "ds1".to_string(),
+            domains: vec!["localhost".to_string()],
+            proxy_address: None,
+            wake_timeout: 30,
+            router_url: None,
+            dams_url: None,
+            advertise_url: None,
+            heartbeat_interval: 10,
+            stacks: vec![],
+            builder_cache: true,
+            default_resources: Resources::default(),
+            max_resources: Resources::default(),
+            default_port: 8080,
+            secret_key_file: "./secret.key".to_string(),
+            max_upload_size: 100,
+        }
+    }
+}
@@ -1,11 +1,25 @@
 #[macro_use] extern crate rocket;
 use std::io;
-use std::io::{Error, ErrorKind};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rocket::{Build, Rocket};
+use rocket::fairing::AdHoc;
 use rocket::response::stream::ReaderStream;
-use rocket::tokio::io::BufReader;
+use command_runner::{CommandRunner, CommandSpec, Output, TokioRunner};
+use crate::config::DsiConfig;
+use crate::utility::build_log::BuildLogs;
+use crate::utility::dams_client::DamsClient;
+use crate::utility::docker::DockerClient;
+use crate::utility::nginx::Nginx;
+use crate::utility::pipeline::{ActiveBuilds, Pipeline, SourceLocks};
+use crate::utility::proxy::Proxy;
+use crate::utility::router_client::RouterClient;
+use crate::utility::secrets::SecretBox;
+use crate::utility::store::DroidStore;
 
 #[cfg(test)] mod integration_tests;
+#[cfg(test)] mod test_support;
+mod config;
 mod routers;
 mod models;
 mod utility;
@@ -22,17 +36,9 @@ fn index() -> &'static str {
 /// Streams the output of a command as a response.
 /// Use "curl -N" to stream the output.
 #[get("/stream")]
-fn stream() -> io::Result<ReaderStream![BufReader<tokio::process::ChildStdout>]> {
-    let stdout = tokio::process::Command::new("journalctl")
-        .arg("-f")
-        .stdout(std::process::Stdio::piped())
-        .spawn()?
-        .stdout
-        .take()
-        .ok_or_else(|| Error::new(ErrorKind::Other,"Could not capture standard output."))?;
-
-    let reader = BufReader::new(stdout);
-    Ok(ReaderStream::one(reader))
+fn stream(runner: &rocket::State<Arc<dyn CommandRunner>>) -> io::Result<ReaderStream![Output]> {
+    let stdout = runner.spawn(&CommandSpec::new("journalctl").arg("-f"))?.stdout;
+    Ok(ReaderStream::one(stdout))
 }
 
 #[get("/state?<name>")]
@@ -41,20 +47,148 @@ fn state(name: String, config: &rocket::State<MyConfig>) -> String {
     let mut lock = myconfig.user_val.lock().unwrap();
     let old_val = lock.to_string();
     *lock = name;
-    format!("Hello, {}! I remember you! You were {}", lock.to_string(), old_val)
+    format!("Hello, {}! I remember you! You were {}", lock, old_val)
 }
 
-#[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
-    let _rocket = rocket::build()
+fn rocket() -> Rocket<Build> {
+    dsi(rocket::build(), Arc::new(TokioRunner))
+}
+
+/// Attaches the DSI state, fairings and routes to `rocket`. External commands are run through `runner`.
+fn dsi(rocket: Rocket<Build>, runner: Arc<dyn CommandRunner>) -> Rocket<Build> {
+    rocket
+        .manage(runner)
+        .manage(BuildLogs::default())
         .manage(MyConfig {
             user_val: Mutex::new("default".to_string()),
         })
+        .attach(AdHoc::config::<DsiConfig>())
+        .attach(AdHoc::try_on_ignite("Droid Store", |rocket| async {
+            let dumps_dir = rocket.state::<DsiConfig>().unwrap().dumps_dir.clone();
+            match DroidStore::load(&dumps_dir) {
+                Ok(store) => Ok(rocket.manage(store)),
+                Err(e) => {
+                    println!("Error loading droid states from {}: {}", dumps_dir, e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::try_on_ignite("Secrets", |rocket| async {
+            let path = rocket.state::<DsiConfig>().unwrap().secret_key_file.clone();
+            match SecretBox::load(&path) {
+                Ok(secrets) => Ok(rocket.manage(secrets)),
+                Err(e) => {
+                    println!("{}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::on_ignite("Docker Client", |rocket| async {
+            let socket = rocket.state::<DsiConfig>().unwrap().docker_socket.clone();
+            rocket.manage(DockerClient::new(&socket))
+        }))
+        .attach(AdHoc::on_ignite("Routing", |rocket| async {
+            let config = rocket.state::<DsiConfig>().unwrap().clone();
+            let runner = rocket.state::<Arc<dyn CommandRunner>>().unwrap().clone();
+            let nginx = Nginx::new(&config, runner.clone());
+            let router = RouterClient::new(&config);
+            let pipeline = Pipeline {
+                store: rocket.state::<DroidStore>().unwrap().clone(),
+                docker: rocket.state::<DockerClient>().unwrap().clone(),
+                runner,
+                network: config.network.clone(),
+                nginx: nginx.clone(),
+                router: router.clone(),
+                secrets: rocket.state::<SecretBox>().unwrap().clone(),
+                config: config.clone(),
+                sources: SourceLocks::default(),
+                builds: ActiveBuilds::default(),
+            };
+            rocket.manage(nginx).manage(router).manage(pipeline)
+        }))
+        .attach(AdHoc::on_ignite("DAMS Client", |rocket| async {
+            let config = rocket.state::<DsiConfig>().unwrap().clone();
+            let rocket_config = rocket.figment().extract::<rocket::Config>().unwrap_or_default();
+            let default_url = format!("http://{}:{}", rocket_config.address, rocket_config.port);
+            rocket.manage(DamsClient::new(&config, default_url))
+        }))
+        .attach(AdHoc::on_liftoff("DAMS Heartbeats", |rocket| Box::pin(async move {
+            let dams = rocket.state::<DamsClient>().unwrap().clone();
+            let store = rocket.state::<DroidStore>().unwrap().clone();
+            tokio::spawn(dams.run(store));
+        })))
+        .attach(AdHoc::on_shutdown("DAMS Deregistration", |rocket| Box::pin(async move {
+            let dams = rocket.state::<DamsClient>().unwrap();
+            if dams.url.is_some() {
+                match dams.deregister().await {
+                    Ok(()) => println!("Deregistered {} from DAMS", dams.server_id),
+                    Err(e) => println!("{}", e)
+                }
+            }
+        })))
+        .attach(AdHoc::on_liftoff("Reconciler", |rocket| Box::pin(async move {
+            let config = rocket.state::<DsiConfig>().unwrap().clone();
+            let store = rocket.state::<DroidStore>().unwrap().clone();
+            let docker = rocket.state::<DockerClient>().unwrap().clone();
+            let runner = rocket.state::<Arc<dyn CommandRunner>>().unwrap().clone();
+            let builds = rocket.state::<Pipeline>().unwrap().builds.clone();
+            tokio::spawn(utility::reconciler::run(store, docker, runner, builds, config.reconcile_interval, config.remove_orphans));
+        })))
+        .attach(AdHoc::on_liftoff("Local Proxy", |rocket| Box::pin(async move {
+            let config = rocket.state::<DsiConfig>().unwrap().clone();
+            let address = match &config.proxy_address {
+                Some(address) => address.clone(),
+                None => return
+            };
+            let proxy = Proxy {
+                store: rocket.state::<DroidStore>().unwrap().clone(),
+                docker: rocket.state::<DockerClient>().unwrap().clone(),
+                nginx: rocket.state::<Nginx>().unwrap().clone(),
+                server_id: config.server_id.clone(),
+                domains: config.domains.clone(),
+                wake_timeout: Duration::from_secs(config.wake_timeout),
+                upstream_host: None,
+            };
+            match tokio::net::TcpListener::bind(&address).await {
+                Ok(listener) => {
+                    println!("Local proxy listening on {}", address);
+                    tokio::spawn(proxy.serve(listener));
+                }
+                Err(e) => println!("Error starting the local proxy on {}: {}", address, e)
+            }
+        })))
         .mount("/", routes![index, stream, state])
-        .mount("/droids", routes![routers::droids_router::new])
+        .mount("/droids", routes![
+            routers::droids_router::new,
+            routers::droids_router::upload_source,
+            routers::droids_router::get,
+            routers::droids_router::build_events,
+            routers::droids_router::logs,
+            routers::droids_router::stats,
+            routers::droids_router::get_env,
+            routers::droids_router::put_env,
+            routers::droids_router::patch_env,
+            routers::droids_router::deploy_key,
+            routers::droids_router::put_git_token,
+            routers::droids_router::get_git_credentials,
+            routers::droids_router::delete_git_credentials,
+            routers::droids_router::start,
+            routers::droids_router::stop,
+            routers::droids_router::restart,
+            routers::droids_router::snooze,
+            routers::droids_router::wake,
+            routers::droids_router::delete,
+        ])
+        .mount("/node", routes![routers::node_router::capacity])
         .mount("/stacks", routes![routers::stacks_router::common])
+        .mount("/reconcile", routes![routers::reconcile_router::plan, routers::reconcile_router::run])
+}
+
+#[rocket::main]
+async fn main() -> Result<(), Box<rocket::Error>> {
+    let _rocket = rocket()
         .launch()
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::io::Write;
 use rocket::serde::{Deserialize, Serialize};
+use command_runner::CommandSpec;
 use crate::models::buildpack::Buildpack;
 use crate::models::order::Order;
 use crate::models::stack::Stack;
@@ -18,24 +19,32 @@ pub struct Builder {
 
 impl Builder {
     /// Creates a builder.toml file and returns the path to the file
-    pub fn save(&self, app_id: String) -> Result<String, Box<dyn std::error::Error>> {
-        let save_path = format!("./dumps/{}/builder.toml", app_id);
-        std::fs::create_dir_all(format!("./dumps/{}", app_id))?;
+    pub fn save(&self, dumps_dir: &str, app_id: String) -> Result<String, Box<dyn std::error::Error>> {
+        let save_path = format!("{}/{}/builder.toml", dumps_dir, app_id);
+        std::fs::create_dir_all(format!("{}/{}", dumps_dir, app_id))?;
         let mut file = File::create(&save_path)?;
         file.write_all(toml::to_string(self)?.as_bytes())?;
         Ok(save_path)
     }
 
-    // Runs "pack builder create <app_id>:<stack.id> --config /app/<app_id>/builder.toml" and return handle to the process
-    pub async fn run_create(&self, app_id: i64) -> Result<tokio::process::Child, std::io::Error> {
-        tokio::process::Command::new("pack")
+    /// Reads the builder.toml saved for the app
+    pub fn load(dumps_dir: &str, app_id: i64) -> Result<Builder, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(format!("{}/{}/builder.toml", dumps_dir, app_id))?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Name of the builder image created for the app
+    pub fn image(&self, app_id: i64) -> String {
+        format!("{}:{}", app_id, self.stack.id)
+    }
+
+    // "pack builder create <app_id>:<stack.id> --config <dumps_dir>/<app_id>/builder.toml"
+    pub fn create_command(&self, dumps_dir: &str, app_id: i64) -> CommandSpec {
+        CommandSpec::new("pack")
             .arg("builder")
             .arg("create")
-            .arg(format!("{}:{}", app_id, self.stack.id))
+            .arg(self.image(app_id))
             .arg("--config")
-            .arg(format!("./dumps/{}/builder.toml", app_id))
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
+            .arg(format!("{}/{}/builder.toml", dumps_dir, app_id))
     }
 }
@@ -17,6 +17,7 @@ pub struct Buildpack {
 // uri = "samples/buildpacks/hello-processes"
 
 impl Buildpack {
+    #[allow(dead_code)]
     pub fn from_uri(uri: &str) -> Result<Buildpack, Box<dyn std::error::Error>> {
         let buildpack = Buildpack {
             uri: uri.to_string(),
@@ -26,21 +27,27 @@ impl Buildpack {
         Ok(buildpack)
     }
 
+    /// The id of the buildpack, its uri without scheme and version. Fails if that leaves nothing.
     pub fn id(&self) -> Result<String, Box<dyn std::error::Error>> {
-        Ok(self.uri.split('@').collect::<Vec<&str>>()[0].split(':').collect::<Vec<&str>>().last().unwrap().to_string())
+        let id = self.uri.split('@').collect::<Vec<&str>>()[0].split(':').collect::<Vec<&str>>().last().unwrap().trim().to_string();
+        if id.is_empty() {
+            return Err(format!("Buildpack `{}` has no id", self.uri).into());
+        }
+        Ok(id)
     }
 
     /// Fetches the buildpack info from the registry, and sets the version and compatible stacks fields.
     /// If no version is found or no compatible stacks are found, then an error is returned.
-    pub async fn validate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn validate(&mut self, registry_url: &str) -> Result<(), Box<dyn std::error::Error>> {
         let data = fetch_buildpack_info(
-            &self.uri.replace("urn:cnb:registry:", "")
-                .split("@").collect::<Vec<&str>>()[0].to_string()
+            registry_url,
+            self.uri.replace("urn:cnb:registry:", "")
+                .split('@').collect::<Vec<&str>>()[0]
         ).await?;
 
         match data["versions"].as_array() {
             Some(versions) => {
-                if versions.len() == 0 {
+                if versions.is_empty() {
                     return Err("No versions found".into());
                 }
                 // check if self.version is in versions
@@ -90,4 +97,17 @@ impl Buildpack {
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_validate_version_fallback() {
+    println!("A known version should be kept, an unknown version should fall back to the latest one");
+    let registry = crate::test_support::registry_url();
+    let mut known = Buildpack { version: Some("0.1.2".to_string()), ..Buildpack::from_uri("urn:cnb:registry:heroku/ruby").unwrap() };
+    let mut unknown = Buildpack { version: Some("9.9.9".to_string()), ..Buildpack::from_uri("heroku/ruby").unwrap() };
+    tokio_test::block_on(known.validate(registry)).unwrap();
+    tokio_test::block_on(unknown.validate(registry)).unwrap();
+
+    assert_eq!(known.version, Some("0.1.2".to_string()));
+    assert_eq!(unknown.version, Some("0.1.3".to_string()));
+    assert_eq!(unknown.compatible_stacks, Some(vec!["heroku-18".to_string(), "heroku-20".to_string()]));
+}
@@ -0,0 +1,106 @@
+use std::path::Path;
+use rocket::serde::Serialize;
+use crate::models::buildpack::Buildpack;
+use crate::models::droid::Droid;
+use crate::models::stack::Stack;
+
+/// Files telling which buildpack builds a repo, in the order the buildpacks run
+const DETECTORS: &[(&str, &str)] = &[
+    ("package.json", "heroku/nodejs"),
+    ("Gemfile", "heroku/ruby"),
+    ("pom.xml", "heroku/java"),
+    ("go.mod", "heroku/go"),
+    ("requirements.txt", "heroku/python"),
+];
+
+/// A repo with none of the `DETECTORS` files but an `index.html` is served as a static site
+const STATIC_SITE: (&str, &str) = ("index.html", "paketo-buildpacks/nginx");
+
+/// A file found at the root of the repo, and the buildpack it calls for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Detection {
+    pub file: String,
+    pub buildpack: String,
+}
+
+/// What a droid without buildpacks is built with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BuildPlan {
+    pub detected: Vec<Detection>,
+    pub buildpacks: Vec<String>,
+    /// Stacks all the buildpacks are compatible with
+    pub common_stacks: Vec<String>,
+    /// Stack id the droid is built on
+    pub stack: String,
+}
+
+/// Inspects the root of the repo cloned in `dir` for the files of `DETECTORS`
+pub fn detect(dir: &str) -> Vec<Detection> {
+    let found = |file: &str| Path::new(dir).join(file).is_file();
+    let mut detections: Vec<Detection> = DETECTORS.iter()
+        .filter(|(file, _)| found(file))
+        .map(|(file, buildpack)| Detection { file: file.to_string(), buildpack: buildpack.to_string() })
+        .collect();
+    if detections.is_empty() && found(STATIC_SITE.0) {
+        detections.push(Detection { file: STATIC_SITE.0.to_string(), buildpack: STATIC_SITE.1.to_string() });
+    }
+    detections
+}
+
+/// Gives the droid the buildpacks detected in `dir`, and the newest known stack they have in common (among the
+/// `stacks` of the server, if any) unless the droid has a stack already.
+pub async fn plan(droid: &mut Droid, dir: &str, registry_url: &str, stacks: &[String]) -> Result<BuildPlan, String> {
+    let detected = detect(dir);
+    if detected.is_empty() {
+        let files: Vec<&str> = DETECTORS.iter().map(|(file, _)| *file).chain([STATIC_SITE.0]).collect();
+        return Err(format!("No buildpack detected, the repo has none of {}", files.join(", ")));
+    }
+    droid.buildpacks = detected.iter()
+        .map(|detection| Buildpack { uri: detection.buildpack.clone(), ..Default::default() })
+        .collect();
+    let common_stacks = droid.detect_common_stacks(registry_url).await.map_err(|e| e.to_string())?;
+    if droid.stack.id.is_empty() {
+        droid.stack = Stack::newest_known(&common_stacks, stacks)
+            .ok_or_else(|| format!("No known stack among {} is supported by this server", common_stacks.join(", ")))?;
+    }
+    Ok(BuildPlan {
+        buildpacks: droid.buildpacks.iter().map(|buildpack| buildpack.uri.clone()).collect(),
+        detected,
+        common_stacks,
+        stack: droid.stack.id.clone(),
+    })
+}
+
+#[test]
+fn test_plan() {
+    println!("A repo with a package.json and a Gemfile should be built with nodejs and ruby on their newest common stack");
+    let dir = std::env::temp_dir().join(format!("dsi-detection-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir_str = dir.to_str().unwrap();
+    std::fs::write(dir.join("index.html"), "").unwrap();
+    assert_eq!(detect(dir_str)[0].buildpack, "paketo-buildpacks/nginx");
+    std::fs::write(dir.join("Gemfile"), "").unwrap();
+    std::fs::write(dir.join("package.json"), "{}").unwrap();
+
+    let mut droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+        "buildpacks": [], "env": []}"#).unwrap();
+    let registry = crate::test_support::registry_url();
+    let chosen = tokio_test::block_on(plan(&mut droid, dir_str, registry, &[])).unwrap();
+    assert_eq!(chosen.detected.iter().map(|d| d.file.as_str()).collect::<Vec<_>>(), vec!["package.json", "Gemfile"]);
+    assert_eq!(chosen.buildpacks, vec!["heroku/nodejs", "heroku/ruby"]);
+    assert_eq!(chosen.common_stacks, vec!["heroku-18", "heroku-20"]);
+    assert_eq!(droid.stack.id, "heroku-20");
+    assert_eq!(droid.stack.run_image, "heroku/heroku:20-cnb");
+
+    println!("The stacks of the server should limit the choice, and a repo without any known file should fail");
+    droid.stack = Stack::default();
+    let chosen = tokio_test::block_on(plan(&mut droid, dir_str, registry, &["heroku-18".to_string()])).unwrap();
+    assert_eq!(chosen.stack, "heroku-18");
+    droid.stack = Stack::default();
+    assert!(tokio_test::block_on(plan(&mut droid, dir_str, registry, &["heroku-22".to_string()])).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(tokio_test::block_on(plan(&mut droid, dir_str, registry, &[])).unwrap_err().starts_with("No buildpack detected"));
+}
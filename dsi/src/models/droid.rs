@@ -1,24 +1,126 @@
+use std::path::{Component, Path};
 use rocket::serde::{Deserialize, Serialize};
 use crate::models::builder;
 use crate::models::buildpack::Buildpack;
+use crate::models::env::{self, EnvVars};
 use crate::models::group::Group;
 use crate::models::order::Order;
+use crate::models::preset::Preset;
+use crate::models::resources::Resources;
 use crate::models::stack::Stack;
+use crate::utility::secrets::REDACTED;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct Droid {
     pub app_id: i64,
-    repo: String,
-    branch: String,
-    buildpacks: Vec<Buildpack>,
-    env: Vec<String>,
+    /// Public name of the app, reported to the global router. Unnamed droids are only reachable at their addresses.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub repo: String,
+    pub branch: String,
+    /// Commit SHA to deploy instead of the head of `branch`
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Subdirectory of the repo the app is built from, for monorepos. The root of the repo if unset.
+    #[serde(default)]
+    pub root_dir: Option<String>,
+    /// May be empty if the preset or the repo tells which buildpacks build the app
+    #[serde(default)]
+    pub buildpacks: Vec<Buildpack>,
+    /// One of the legacy app types, expanded into the buildpacks, build env and PORT the request does not set
+    #[serde(default)]
+    pub preset: Option<Preset>,
+    /// Runtime environment of the container, as `KEY=VALUE` pairs. PORT is added if missing.
+    pub env: Vec<String>,
+    /// Keys of `env` holding secrets: encrypted at rest, and redacted from the logs and API responses
+    #[serde(default)]
+    pub secrets: Vec<String>,
+    /// Environment of the build only, passed to `pack build --env`
+    #[serde(default)]
+    pub build_env: Vec<String>,
+    /// Requested resource limits, completed with the defaults of the server
+    #[serde(default)]
+    pub resources: Resources,
+    /// May be omitted if the `appoxy.toml` of the repo declares it
+    #[serde(default)]
     pub stack: Stack, // Stack to be used for the builder, use the detect_common_stacks function to find a compatible stack for the buildpacks
 }
 
+/// Redacts the values of the secrets, so that droids can be logged
+impl std::fmt::Debug for Droid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Droid")
+            .field("app_id", &self.app_id)
+            .field("name", &self.name)
+            .field("repo", &self.repo)
+            .field("branch", &self.branch)
+            .field("commit", &self.commit)
+            .field("root_dir", &self.root_dir)
+            .field("buildpacks", &self.buildpacks)
+            .field("preset", &self.preset)
+            .field("env", &self.redacted_env())
+            .field("secrets", &self.secrets)
+            .field("build_env", &self.build_env)
+            .field("resources", &self.resources)
+            .field("stack", &self.stack)
+            .finish()
+    }
+}
+
 impl Droid {
-    pub async fn detect_common_stacks(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        Stack::detect_common_stacks(&mut self.buildpacks).await
+    /// Validates the runtime and build environments, and adds PORT to the runtime one if it is missing.
+    /// Returns them with the port the droid listens on.
+    pub fn parse_env(&self, default_port: u16) -> Result<(EnvVars, EnvVars, u16), String> {
+        let mut runtime = env::parse(&self.env)?;
+        let port = env::with_port(&mut runtime, default_port)?;
+        let build = env::parse(&self.build_env).map_err(|e| format!("build_env: {}", e))?;
+        for secret in &self.secrets {
+            env::check_secret(secret, &runtime)?;
+        }
+        Ok((runtime, build, port))
+    }
+
+    /// The runtime environment with the values of the secrets redacted
+    pub fn redacted_env(&self) -> Vec<String> {
+        self.env.iter().map(|var| match var.split_once('=') {
+            Some((key, _)) if self.secrets.iter().any(|secret| secret == key) => format!("{}={}", key, REDACTED),
+            _ => var.clone(),
+        }).collect()
+    }
+
+    /// The `commit` in lowercase, None if unset or empty. Fails if it is not a full or abbreviated SHA.
+    pub fn commit(&self) -> Result<Option<String>, String> {
+        let commit = match self.commit.as_deref().map(str::trim) {
+            Some(commit) if !commit.is_empty() => commit.to_ascii_lowercase(),
+            _ => return Ok(None),
+        };
+        if !(7..=64).contains(&commit.len()) || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("commit must be a SHA of 7 to 64 hexadecimal digits, got `{}`", commit));
+        }
+        Ok(Some(commit))
+    }
+
+    /// The `root_dir` relative to the root of the repo, without leading `./` or `/` and trailing `/`. None for the root
+    /// itself. Fails if it could leave the repo.
+    pub fn root_dir(&self) -> Result<Option<String>, String> {
+        let dir = match &self.root_dir {
+            Some(dir) => dir.trim().trim_start_matches("./").trim_start_matches('/').trim_end_matches('/'),
+            None => return Ok(None),
+        };
+        let mut parts = vec![];
+        for component in Path::new(dir).components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+                Component::CurDir => {}
+                _ => return Err(format!("root_dir must be a directory inside the repo, got `{}`", dir)),
+            }
+        }
+        Ok(if parts.is_empty() { None } else { Some(parts.join("/")) })
+    }
+
+    pub async fn detect_common_stacks(&mut self, registry_url: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Stack::detect_common_stacks(registry_url, &mut self.buildpacks).await
     }
 
     pub async fn create_builder(&self) -> Result<builder::Builder, String> {
@@ -26,17 +128,67 @@ impl Droid {
             buildpacks: self.buildpacks.clone(),
             stack: self.stack.clone(),
             description: Some("Created by Droid".to_string()),
-            order: self.buildpacks.iter().map(|buildpack| Order{
+            order: self.buildpacks.iter().map(|buildpack| Ok(Order{
                 group: vec![Group {
-                    id: match buildpack.id() {
-                        Ok(x) => x,
-                        Err(_) => todo!(),
-                    },
+                    id: buildpack.id().map_err(|e| e.to_string())?,
                     optional: buildpack.optional
                 }]
-            }).collect::<Vec<Order>>()
+            })).collect::<Result<Vec<Order>, String>>()?
         };
 
         Ok(builder)
     }
 }
+
+#[test]
+fn test_debug() {
+    println!("Secrets should be redacted from the debug output of a droid");
+    let droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main", "buildpacks": [],
+        "env": ["DB_PASSWORD=hunter2", "FOO=bar"], "secrets": ["DB_PASSWORD"], "stack": {"id": "heroku-20", "build-image": "", "run-image": ""}}"#).unwrap();
+    let debug = format!("{:?}", droid);
+    assert!(!debug.contains("hunter2"));
+    assert!(debug.contains("DB_PASSWORD=********") && debug.contains("FOO=bar"));
+}
+
+#[test]
+fn test_root_dir() {
+    println!("root_dir should be normalized, and rejected if it could leave the repo");
+    let root_dir = |dir: &str| {
+        let mut droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+            "env": []}"#).unwrap();
+        droid.root_dir = Some(dir.to_string());
+        droid.root_dir()
+    };
+    assert_eq!(root_dir("./apps/web/"), Ok(Some("apps/web".to_string())));
+    assert_eq!(root_dir("/apps/./api"), Ok(Some("apps/api".to_string())));
+    assert_eq!(root_dir("."), Ok(None));
+    assert!(root_dir("../other").is_err());
+    assert!(root_dir("apps/../../other").is_err());
+}
+
+#[test]
+fn test_commit() {
+    println!("commit should be a SHA, lowercased, an empty one meaning the head of the branch");
+    let commit = |commit: &str| {
+        let mut droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+            "env": []}"#).unwrap();
+        droid.commit = Some(commit.to_string());
+        droid.commit()
+    };
+    assert_eq!(commit(" 3F2A9C1 "), Ok(Some("3f2a9c1".to_string())));
+    assert_eq!(commit(""), Ok(None));
+    assert!(commit("3f2a9").is_err());
+    assert!(commit("main").is_err());
+    assert!(commit("3f2a9c1 --upload-pack=x").is_err());
+}
+
+#[test]
+fn test_create_builder() {
+    println!("Every buildpack should be a group of the builder order, a buildpack without id failing the creation");
+    let mut droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+        "buildpacks": [{"uri": "urn:cnb:registry:heroku/nodejs@0.5.0"}, {"uri": "heroku/ruby"}], "env": []}"#).unwrap();
+    let builder = tokio_test::block_on(droid.create_builder()).unwrap();
+    assert_eq!(builder.order.iter().map(|o| o.group[0].id.clone()).collect::<Vec<String>>(), vec!["heroku/nodejs", "heroku/ruby"]);
+    droid.buildpacks[1].uri = "urn:cnb:registry:".to_string();
+    assert_eq!(tokio_test::block_on(droid.create_builder()).err(), Some("Buildpack `urn:cnb:registry:` has no id".to_string()));
+}
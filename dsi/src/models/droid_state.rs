@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use app_address::AppAddress;
+use rocket::serde::{Deserialize, Serialize};
+use crate::models::env::EnvVars;
+use crate::models::manifest::AppConfig;
+use crate::models::resources::Resources;
+use crate::utility::lifecycle::PhaseTiming;
+use crate::utility::secrets;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum DroidStatus {
+    Building,
+    /// The app image is built, its container is not deployed yet
+    Built,
+    Running,
+    Stopped,
+    Snoozed,
+    Failed,
+}
+
+/// The recorded state of a droid, dumped to `<dumps_dir>/<app_id>/state.toml`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DroidState {
+    /// External id of the app, only known to the DSI and its clients
+    pub app_id: i64,
+    /// Short internal id of the droid, names its container and is the first label of its hostnames.
+    /// States dumped before uids existed get their app id as uid when loaded.
+    #[serde(default)]
+    pub uid: String,
+    /// Public name of the app, if any
+    #[serde(default)]
+    pub name: Option<String>,
+    pub status: DroidStatus,
+    /// Builder image created for the droid, i.e. "<app_id>:<stack>"
+    pub builder: Option<String>,
+    /// Image built for the droid, i.e. "<app_id>:latest"
+    pub image: Option<String>,
+    /// Identifier of the source the image was built from: the commit SHA of the repo, or `sha256:<digest>` of an
+    /// uploaded archive
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Digest of the source, builder and environment the image was built from, a build with the same one only
+    /// restarts the droid
+    #[serde(default)]
+    pub build_digest: Option<String>,
+    /// Name of the droid container, once it has been created
+    pub container: Option<String>,
+    /// PID of the running build process, if any
+    pub build_pid: Option<u32>,
+    /// Machine-readable reason of the last failure, i.e. "no_buildpack_detected"
+    pub error_code: Option<String>,
+    /// Reason of the last failure
+    pub error: Option<String>,
+    /// Port the droid listens on, from its PORT environment variable
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Keys of `env` holding secrets, whose values are encrypted with the server key
+    #[serde(default)]
+    pub secrets: BTreeSet<String>,
+    /// Lifecycle phases of the last build
+    #[serde(default)]
+    pub phases: Vec<PhaseTiming>,
+    /// Resource limits the droid container is created with
+    #[serde(default)]
+    pub resources: Resources,
+    /// Runtime environment of the container, PORT included
+    #[serde(default)]
+    pub env: EnvVars,
+    /// Environment of the last build
+    #[serde(default)]
+    pub build_env: EnvVars,
+    /// Effective config, merged from the request and the `appoxy.toml` of the repo
+    #[serde(default)]
+    pub config: AppConfig,
+}
+
+impl DroidState {
+    pub fn new(app_id: i64, uid: &str) -> DroidState {
+        DroidState {
+            app_id,
+            uid: uid.to_string(),
+            name: None,
+            status: DroidStatus::Building,
+            builder: None,
+            image: None,
+            source: None,
+            build_digest: None,
+            container: None,
+            build_pid: None,
+            error_code: None,
+            error: None,
+            port: None,
+            secrets: BTreeSet::new(),
+            phases: Vec::new(),
+            resources: Resources::default(),
+            env: EnvVars::new(),
+            build_env: EnvVars::new(),
+            config: AppConfig::default(),
+        }
+    }
+
+    /// The addresses the droid is served at, one per domain. Droids without a port have none.
+    pub fn addresses(&self, server_id: &str, domains: &[String]) -> Vec<AppAddress> {
+        let port = match self.port {
+            Some(port) => port,
+            None => return Vec::new()
+        };
+        domains.iter().filter_map(|domain| match AppAddress::new(&self.uid, port, server_id, domain) {
+            Ok(address) => Some(address),
+            Err(e) => {
+                println!("Droid {} cannot be routed: {}", self.app_id, e);
+                None
+            }
+        }).collect()
+    }
+
+    /// The state with the values of its secrets redacted, for API responses
+    pub fn redacted(&self) -> DroidState {
+        DroidState { env: secrets::redact(&self.env, &self.secrets), ..self.clone() }
+    }
+
+    /// Marks the droid as failed, forgetting about its build process.
+    pub fn fail(&mut self, code: &str, reason: &str) {
+        self.status = DroidStatus::Failed;
+        self.build_pid = None;
+        self.error_code = Some(code.to_string());
+        self.error = Some(reason.to_string());
+    }
+
+    /// Writes the state to `<dumps_dir>/<app_id>/state.toml` and returns the path to the file
+    pub fn save(&self, dumps_dir: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let dir = format!("{}/{}", dumps_dir, self.app_id);
+        std::fs::create_dir_all(&dir)?;
+        let save_path = format!("{}/state.toml", dir);
+        let mut file = File::create(&save_path)?;
+        file.write_all(toml::to_string(self)?.as_bytes())?;
+        Ok(save_path)
+    }
+
+    pub fn load(path: &Path) -> Result<DroidState, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
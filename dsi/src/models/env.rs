@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+/// Validated environment variables, by key
+pub type EnvVars = BTreeMap<String, String>;
+
+/// Keys are made of letters, digits and underscores, and do not start with a digit
+pub fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses `KEY=VALUE` pairs, rejecting invalid keys and keys given twice. Values may be empty or contain `=`.
+pub fn parse(vars: &[String]) -> Result<EnvVars, String> {
+    let mut env = EnvVars::new();
+    for var in vars {
+        let (key, value) = var.split_once('=').ok_or_else(|| format!("{} is not a KEY=VALUE pair", var))?;
+        check_key(key)?;
+        if env.insert(key.to_string(), value.to_string()).is_some() {
+            return Err(format!("{} is set more than once", key));
+        }
+    }
+    Ok(env)
+}
+
+pub fn check_key(key: &str) -> Result<(), String> {
+    match is_valid_key(key) {
+        true => Ok(()),
+        false => Err(format!("{} is not a valid variable name", key)),
+    }
+}
+
+/// Secrets have to be variables of `env`. PORT is needed in plain text for routing, and cannot be one.
+pub fn check_secret(key: &str, env: &EnvVars) -> Result<(), String> {
+    match (key, env.contains_key(key)) {
+        ("PORT", _) => Err("PORT cannot be a secret".to_string()),
+        (_, false) => Err(format!("Secret {} is not set in env", key)),
+        _ => Ok(()),
+    }
+}
+
+/// Sets PORT to `default_port` if it is missing, and returns the port the droid listens on
+pub fn with_port(env: &mut EnvVars, default_port: u16) -> Result<u16, String> {
+    let port = env.entry("PORT".to_string()).or_insert_with(|| default_port.to_string());
+    match port.trim().parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(format!("PORT must be a port number, got {}", port)),
+    }
+}
+
+/// The `KEY=VALUE` list docker and `pack` take
+pub fn to_list(env: &EnvVars) -> Vec<String> {
+    env.iter().map(|(key, value)| format!("{}={}", key, value)).collect()
+}
+
+#[test]
+fn test_parse() {
+    println!("Variables should be parsed into a map, rejecting invalid keys and duplicates");
+    let vars = |vars: &[&str]| vars.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+    let env = parse(&vars(&["FOO=bar", "DATABASE_URL=postgres://u:p@db/app?a=b", "EMPTY="])).unwrap();
+    assert_eq!(env["DATABASE_URL"], "postgres://u:p@db/app?a=b");
+    assert_eq!(env["EMPTY"], "");
+    assert_eq!(to_list(&env), vars(&["DATABASE_URL=postgres://u:p@db/app?a=b", "EMPTY=", "FOO=bar"]));
+
+    assert!(parse(&vars(&["FOO"])).is_err());
+    assert!(parse(&vars(&["1FOO=bar"])).is_err());
+    assert!(parse(&vars(&["FOO-BAR=baz"])).is_err());
+    assert_eq!(parse(&vars(&["FOO=1", "FOO=2"])), Err("FOO is set more than once".to_string()));
+
+    println!("PORT should be injected if missing, and validated otherwise");
+    let mut env = parse(&vars(&["FOO=bar"])).unwrap();
+    assert_eq!(with_port(&mut env, 8080), Ok(8080));
+    assert_eq!(env["PORT"], "8080");
+    let mut env = parse(&vars(&["PORT=7000"])).unwrap();
+    assert_eq!(with_port(&mut env, 8080), Ok(7000));
+    let mut env = parse(&vars(&["PORT=http"])).unwrap();
+    assert!(with_port(&mut env, 8080).is_err());
+}
@@ -0,0 +1,143 @@
+use std::io::Write;
+use std::path::Path;
+use rocket::serde::{Deserialize, Serialize};
+use command_runner::{CommandRunner, CommandSpec};
+use crate::utility::secrets::{SecretBox, REDACTED};
+
+/// How the DSI authenticates the clones of a private repo, dumped to `<dumps_dir>/<app_id>/git_credentials.toml`.
+/// The secret halves are encrypted with the server key, and only decrypted for `git clone`. They can be set before the
+/// droid is first deployed, and are removed with its dumps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GitCredentials {
+    /// An SSH key generated by the DSI, whose public half is added to the repo as a deploy key
+    DeployKey { public_key: String, private_key: String },
+    /// An HTTPS token, i.e. a GitHub personal access token
+    Token { username: String, token: String },
+}
+
+/// Body of `PUT /droids/<app_id>/git-token`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct GitToken {
+    #[serde(default)]
+    pub username: Option<String>,
+    pub token: String,
+}
+
+/// File name of the credentials, in the dumps directory of the app
+pub const GIT_CREDENTIALS: &str = "git_credentials.toml";
+
+/// Username of the HTTPS tokens that do not come with one, as GitHub and GitLab accept it
+pub const TOKEN_USERNAME: &str = "x-access-token";
+
+impl GitCredentials {
+    fn path(dumps_dir: &str, app_id: i64) -> String {
+        format!("{}/{}/{}", dumps_dir, app_id, GIT_CREDENTIALS)
+    }
+
+    /// The credentials of the app, None if it has none
+    pub fn load(dumps_dir: &str, app_id: i64) -> Result<Option<GitCredentials>, String> {
+        let path = GitCredentials::path(dumps_dir, app_id);
+        if !Path::new(&path).is_file() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+        toml::from_str(&contents).map(Some).map_err(|e| format!("Invalid {}: {}", path, e))
+    }
+
+    /// Replaces the credentials of the app, readable by the DSI user only
+    pub fn save(&self, dumps_dir: &str, app_id: i64) -> Result<(), String> {
+        let path = GitCredentials::path(dumps_dir, app_id);
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        write_private(&path, &contents)
+    }
+
+    pub fn remove(dumps_dir: &str, app_id: i64) -> Result<bool, String> {
+        let path = GitCredentials::path(dumps_dir, app_id);
+        if !Path::new(&path).is_file() {
+            return Ok(false);
+        }
+        std::fs::remove_file(&path).map(|_| true).map_err(|e| e.to_string())
+    }
+
+    /// The credentials without their secret halves, for API responses
+    pub fn redacted(&self) -> GitCredentials {
+        match self {
+            GitCredentials::DeployKey { public_key, .. } => GitCredentials::DeployKey {
+                public_key: public_key.clone(),
+                private_key: REDACTED.to_string(),
+            },
+            GitCredentials::Token { username, .. } => GitCredentials::Token {
+                username: username.clone(),
+                token: REDACTED.to_string(),
+            },
+        }
+    }
+
+    /// Generates an ed25519 deploy key for the app with `ssh-keygen`, its private half encrypted with `secrets`
+    pub async fn generate(runner: &dyn CommandRunner, dumps_dir: &str, app_id: i64, secrets: &SecretBox) -> Result<GitCredentials, String> {
+        let dir = format!("{}/{}/keygen", dumps_dir, app_id);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let command = CommandSpec::new("ssh-keygen")
+            .arg("-q")
+            .arg("-t").arg("ed25519")
+            .arg("-N").arg("")
+            .arg("-C").arg(format!("appoxy-{}", app_id))
+            .arg("-f").arg("id_ed25519")
+            .dir(&dir);
+        let result = command_runner::output(runner, &command).await.map_err(|e| format!("Error running ssh-keygen: {}", e))
+            .and_then(|output| match output.code {
+                0 => Ok(()),
+                code => Err(format!("ssh-keygen exited with code {}: {}", code, output.stderr.trim())),
+            })
+            .and_then(|_| {
+                let read = |name: &str| std::fs::read_to_string(format!("{}/{}", dir, name)).map_err(|e| e.to_string());
+                Ok(GitCredentials::DeployKey {
+                    public_key: read("id_ed25519.pub")?.trim().to_string(),
+                    private_key: secrets.encrypt(&read("id_ed25519")?),
+                })
+            });
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+}
+
+/// Writes `contents` to `path`, readable and writable by the DSI user only
+pub fn write_private(path: &str, contents: &str) -> Result<(), String> {
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let _ = std::fs::remove_file(path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| format!("Error writing {}: {}", path, e))
+}
+
+#[test]
+fn test_credentials() {
+    println!("Credentials should be dumped readable by the DSI only, and redacted for responses");
+    let dumps_dir = std::env::temp_dir().join(format!("dsi-git-credentials-{}", std::process::id()));
+    let dumps_dir = dumps_dir.to_str().unwrap();
+    let _ = std::fs::remove_dir_all(dumps_dir);
+    assert_eq!(GitCredentials::load(dumps_dir, 1), Ok(None));
+    let credentials = GitCredentials::Token { username: TOKEN_USERNAME.to_string(), token: "enc:v1:abc".to_string() };
+    credentials.save(dumps_dir, 1).unwrap();
+    assert_eq!(GitCredentials::load(dumps_dir, 1), Ok(Some(credentials.clone())));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(format!("{}/1/git_credentials.toml", dumps_dir)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    assert_eq!(credentials.redacted(), GitCredentials::Token { username: TOKEN_USERNAME.to_string(), token: REDACTED.to_string() });
+    assert_eq!(GitCredentials::remove(dumps_dir, 1), Ok(true));
+    assert_eq!(GitCredentials::remove(dumps_dir, 1), Ok(false));
+    std::fs::remove_dir_all(dumps_dir).unwrap();
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use rocket::serde::{Deserialize, Serialize};
+use crate::models::buildpack::Buildpack;
+use crate::models::droid::Droid;
+use crate::models::resources::Resources;
+use crate::models::stack::Stack;
+
+/// File name of the app manifest, at the root of the repo
+pub const MANIFEST: &str = "appoxy.toml";
+
+/// When an idle droid is snoozed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct SnoozePolicy {
+    pub enabled: bool,
+    /// Seconds without requests after which the droid is snoozed
+    pub idle_timeout: u64,
+}
+
+impl Default for SnoozePolicy {
+    fn default() -> Self {
+        SnoozePolicy { enabled: true, idle_timeout: 1800 }
+    }
+}
+
+/// How to tell that the droid is up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct HealthCheck {
+    /// Path requested on the droid port, a 2xx or 3xx response meaning healthy
+    pub path: String,
+    /// Seconds between two checks
+    pub interval: u64,
+    /// Seconds a check may take
+    pub timeout: u64,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck { path: "/".to_string(), interval: 30, timeout: 5 }
+    }
+}
+
+/// The optional `appoxy.toml` of a repo, declaring how the app is built and run:
+///
+/// ```toml
+/// port = 3000
+///
+/// [[buildpacks]]
+/// uri = "heroku/nodejs"
+///
+/// [stack]
+/// id = "heroku-20"
+/// build-image = "heroku/heroku:20-cnb-build"
+/// run-image = "heroku/heroku:20-cnb"
+///
+/// [env]
+/// NODE_ENV = "production"
+///
+/// [resources]
+/// memory = 512
+///
+/// [snooze]
+/// idle_timeout = 900
+///
+/// [health_check]
+/// path = "/health"
+/// ```
+///
+/// Everything the `Droid` request sets wins over the manifest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub port: Option<u16>,
+    pub buildpacks: Vec<Buildpack>,
+    pub stack: Option<Stack>,
+    /// Defaults of the runtime environment
+    pub env: BTreeMap<String, String>,
+    pub resources: Resources,
+    pub snooze: Option<SnoozePolicy>,
+    pub health_check: Option<HealthCheck>,
+}
+
+/// The config a droid is built and run with, once the request and the manifest are merged. The port, environment
+/// and resource limits are kept on the droid state itself.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct AppConfig {
+    /// Whether the repo has an `appoxy.toml`
+    pub manifest: bool,
+    /// Whether the repo has a `project.toml`
+    pub project_descriptor: bool,
+    /// Buildpack uris
+    pub buildpacks: Vec<String>,
+    /// Stack id
+    pub stack: String,
+    /// Subdirectory of the repo the app is built from
+    pub root_dir: Option<String>,
+    pub snooze: Option<SnoozePolicy>,
+    pub health_check: Option<HealthCheck>,
+}
+
+impl AppConfig {
+    pub fn of(droid: &Droid, manifest: Option<&Manifest>) -> AppConfig {
+        AppConfig {
+            manifest: manifest.is_some(),
+            project_descriptor: false,
+            buildpacks: droid.buildpacks.iter().map(|buildpack| buildpack.uri.clone()).collect(),
+            stack: droid.stack.id.clone(),
+            root_dir: droid.root_dir.clone(),
+            snooze: manifest.and_then(|m| m.snooze.clone()),
+            health_check: manifest.and_then(|m| m.health_check.clone()),
+        }
+    }
+}
+
+impl Manifest {
+    /// Reads `<dir>/appoxy.toml`, None if the repo has none
+    pub fn load(dir: &str) -> Result<Option<Manifest>, String> {
+        let path = Path::new(dir).join(MANIFEST);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", MANIFEST, e))?;
+        toml::from_str(&contents).map(Some).map_err(|e| format!("Invalid {}: {}", MANIFEST, e))
+    }
+
+    /// Completes the droid with the manifest, keeping everything the request set. Returns true if the buildpacks
+    /// or the stack came from the manifest, meaning that the builder has to be created again.
+    pub fn merge_into(&self, droid: &mut Droid) -> bool {
+        let mut builder_changed = false;
+        if droid.buildpacks.is_empty() && !self.buildpacks.is_empty() {
+            droid.buildpacks = self.buildpacks.clone();
+            builder_changed = true;
+        }
+        if let (true, Some(stack)) = (droid.stack.id.is_empty(), &self.stack) {
+            droid.stack = stack.clone();
+            builder_changed = true;
+        }
+
+        let requested = |key: &str| droid.env.iter().any(|var| var.split_once('=').map(|(k, _)| k) == Some(key));
+        let mut defaults: Vec<String> = self.env.iter()
+            .filter(|(key, _)| !requested(key))
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        if let (Some(port), false, false) = (self.port, requested("PORT"), self.env.contains_key("PORT")) {
+            defaults.push(format!("PORT={}", port));
+        }
+        droid.env.extend(defaults);
+
+        let resources = &mut droid.resources;
+        resources.cpu = resources.cpu.or(self.resources.cpu);
+        resources.memory = resources.memory.or(self.resources.memory);
+        resources.memory_swap = resources.memory_swap.or(self.resources.memory_swap);
+        resources.pids = resources.pids.or(self.resources.pids);
+        builder_changed
+    }
+}
+
+#[test]
+fn test_merge() {
+    println!("The manifest should only fill in what the request did not set");
+    let manifest: Manifest = toml::from_str(r#"
+        port = 3000
+
+        [[buildpacks]]
+        uri = "heroku/nodejs"
+
+        [stack]
+        id = "heroku-20"
+        build-image = "heroku/heroku:20-cnb-build"
+        run-image = "heroku/heroku:20-cnb"
+
+        [env]
+        NODE_ENV = "production"
+        LOG_LEVEL = "info"
+
+        [resources]
+        memory = 512
+        cpu = 0.5
+
+        [snooze]
+        idle_timeout = 900
+
+        [health_check]
+        path = "/health"
+    "#).unwrap();
+    assert_eq!(manifest.snooze, Some(SnoozePolicy { enabled: true, idle_timeout: 900 }));
+    assert_eq!(manifest.health_check.as_ref().unwrap().interval, 30);
+
+    let mut droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+        "buildpacks": [], "env": ["LOG_LEVEL=debug"], "resources": {"memory": 1024}}"#).unwrap();
+    assert!(manifest.merge_into(&mut droid));
+    assert_eq!(droid.buildpacks[0].uri, "heroku/nodejs");
+    assert_eq!(droid.stack.id, "heroku-20");
+    assert_eq!(droid.env, vec!["LOG_LEVEL=debug", "NODE_ENV=production", "PORT=3000"]);
+    assert_eq!(droid.resources, Resources { cpu: Some(0.5), memory: Some(1024), ..Default::default() });
+    let config = AppConfig::of(&droid, Some(&manifest));
+    assert_eq!(config.buildpacks, vec!["heroku/nodejs"]);
+    assert_eq!(config.health_check.unwrap().path, "/health");
+
+    println!("A request setting the buildpacks, stack and PORT should keep its builder and port");
+    let mut droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+        "buildpacks": [{"uri": "heroku/go"}], "env": ["PORT=8000"], "stack": {"id": "heroku-22", "build-image": "", "run-image": ""}}"#).unwrap();
+    assert!(!manifest.merge_into(&mut droid));
+    assert_eq!(droid.stack.id, "heroku-22");
+    assert_eq!(droid.env, vec!["PORT=8000", "LOG_LEVEL=info", "NODE_ENV=production"]);
+
+    assert!(toml::from_str::<Manifest>("prot = 3000").is_err());
+}
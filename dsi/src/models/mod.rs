@@ -4,3 +4,11 @@ pub mod group;
 pub mod buildpack;
 pub mod order;
 pub mod stack;
+pub mod droid_state;
+pub mod env;
+pub mod manifest;
+pub mod project_descriptor;
+pub mod detection;
+pub mod preset;
+pub mod resources;
+pub mod git_credentials;
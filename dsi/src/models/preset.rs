@@ -0,0 +1,163 @@
+use rocket::serde::{Deserialize, Serialize};
+use crate::models::buildpack::Buildpack;
+use crate::models::droid::Droid;
+use crate::models::stack::Stack;
+
+/// Stack the presets are built on, all their buildpacks support it
+const PRESET_STACK: &str = "io.buildpacks.stacks.jammy";
+
+/// Build env of a preset, as key and value pairs
+type BuildEnv = Vec<(&'static str, String)>;
+
+/// The app types of the legacy `configure_app.sh`, expanded into buildpacks and build env:
+///
+/// - `node`: a Node.js app with a start command, i.e. Discord bots, Express servers
+/// - `frontend`: an app built into static files, i.e. Angular, React, Vue
+/// - `static`: static files ready to be served
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Preset {
+    Node {
+        start_command: String,
+        /// A script of `package.json` run after the install, i.e. `npm run build`
+        #[serde(default)]
+        build_command: Option<String>,
+        /// Defaults PORT, the server `default_port` being used otherwise
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    Frontend {
+        /// A script of `package.json` building the app, i.e. `npm run build`
+        build_command: String,
+        /// Directory of the built files, relative to the repo
+        publish_dir: String,
+    },
+    Static {
+        /// Directory of the files, relative to the repo
+        publish_dir: String,
+    },
+}
+
+impl Preset {
+    /// Completes the droid with the buildpacks, stack, build env and PORT of the preset, keeping everything the
+    /// request set. Fails like `configure_app.sh` did if a field the app type requires is empty.
+    pub fn expand_into(&self, droid: &mut Droid) -> Result<(), String> {
+        let (buildpacks, build_env, port) = self.expand()?;
+        if droid.buildpacks.is_empty() {
+            droid.buildpacks = buildpacks.iter().map(|uri| Buildpack { uri: uri.to_string(), ..Default::default() }).collect();
+        }
+        if droid.stack.id.is_empty() {
+            droid.stack = Stack::known(PRESET_STACK).unwrap_or_default();
+        }
+        let requested = |vars: &[String], key: &str| vars.iter().any(|var| var.split_once('=').map(|(k, _)| k) == Some(key));
+        let defaults: Vec<String> = build_env.iter()
+            .filter(|(key, _)| !requested(&droid.build_env, key))
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        droid.build_env.extend(defaults);
+        if let (Some(port), false) = (port, requested(&droid.env, "PORT")) {
+            droid.env.push(format!("PORT={}", port));
+        }
+        Ok(())
+    }
+
+    /// Buildpack uris, build env and PORT of the preset
+    fn expand(&self) -> Result<(Vec<&'static str>, BuildEnv, Option<u16>), String> {
+        match self {
+            Preset::Node { start_command, build_command, port } => {
+                if start_command.trim().is_empty() {
+                    return Err("Start Command is required for node apps".to_string());
+                }
+                let mut build_env = vec![("BP_PROCFILE_DEFAULT_PROCESS", start_command.trim().to_string())];
+                if let Some(command) = build_command.as_deref().filter(|command| !command.trim().is_empty()) {
+                    build_env.push(("BP_NODE_RUN_SCRIPTS", script_of(command)?));
+                }
+                Ok((vec!["paketo-buildpacks/nodejs"], build_env, *port))
+            }
+            Preset::Frontend { build_command, publish_dir } => {
+                if build_command.trim().is_empty() {
+                    return Err("Build Command is required for frontend apps".to_string());
+                }
+                let root = publish_root(publish_dir).ok_or("Publish directory is required for frontend apps")?;
+                let mut build_env = vec![("BP_NODE_RUN_SCRIPTS", script_of(build_command)?)];
+                build_env.extend(web_server(root));
+                Ok((vec!["paketo-buildpacks/web-servers"], build_env, None))
+            }
+            Preset::Static { publish_dir } => {
+                let root = publish_root(publish_dir).ok_or("Publish directory is required for static apps")?;
+                Ok((vec!["paketo-buildpacks/web-servers"], web_server(root), None))
+            }
+        }
+    }
+}
+
+/// The nginx config of the web server presets: serve `root`, falling back to `index.html` like the legacy
+/// `try_files $uri $uri/ /index.html`
+fn web_server(root: String) -> BuildEnv {
+    vec![
+        ("BP_WEB_SERVER", "nginx".to_string()),
+        ("BP_WEB_SERVER_ROOT", root),
+        ("BP_WEB_SERVER_ENABLE_PUSH_STATE", "true".to_string()),
+    ]
+}
+
+/// The publish directory relative to the repo, trimmed like `configure_app.sh` did. The root of the repo (`.`, `/`,
+/// `~`) counts as no directory, None.
+fn publish_root(dir: &str) -> Option<String> {
+    let dir = dir.trim();
+    let dir = dir.strip_prefix("./").or_else(|| dir.strip_prefix("~/")).unwrap_or(dir);
+    let dir = dir.trim_start_matches(['/', '~']).trim_end_matches('/');
+    match dir {
+        "" | "." => None,
+        dir => Some(dir.to_string()),
+    }
+}
+
+/// The `package.json` script a build command runs, as `BP_NODE_RUN_SCRIPTS` takes script names
+fn script_of(command: &str) -> Result<String, String> {
+    let command = command.trim();
+    let script = ["npm run ", "yarn run ", "pnpm run ", "yarn "].iter()
+        .find_map(|prefix| command.strip_prefix(prefix))
+        .map(|script| script.trim());
+    match script {
+        Some(script) if !script.is_empty() && !script.contains(char::is_whitespace) => Ok(script.to_string()),
+        _ => Err(format!("Build Command must run a script of package.json, like `npm run build`, got `{}`", command)),
+    }
+}
+
+#[test]
+fn test_expand() {
+    println!("Presets should expand into buildpacks, build env and PORT, keeping what the request set");
+    let droid = |preset: &str, extra: &str| -> Droid {
+        rocket::serde::json::serde_json::from_str(&format!(r#"{{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+            "preset": {}, "env": []{}}}"#, preset, extra)).unwrap()
+    };
+    let mut node = droid(r#"{"type": "node", "start_command": "node index.js", "build_command": "npm run build", "port": 3000}"#, "");
+    node.preset.clone().unwrap().expand_into(&mut node).unwrap();
+    assert_eq!(node.buildpacks[0].uri, "paketo-buildpacks/nodejs");
+    assert_eq!(node.stack.id, "io.buildpacks.stacks.jammy");
+    assert_eq!(node.build_env, vec!["BP_PROCFILE_DEFAULT_PROCESS=node index.js", "BP_NODE_RUN_SCRIPTS=build"]);
+    assert_eq!(node.env, vec!["PORT=3000"]);
+
+    let mut frontend = droid(r#"{"type": "frontend", "build_command": "yarn build", "publish_dir": "./dist/"}"#,
+        r#", "build_env": ["BP_WEB_SERVER=httpd"]"#);
+    frontend.env.push("PORT=9000".to_string());
+    frontend.preset.clone().unwrap().expand_into(&mut frontend).unwrap();
+    assert_eq!(frontend.buildpacks[0].uri, "paketo-buildpacks/web-servers");
+    assert_eq!(frontend.build_env, vec!["BP_WEB_SERVER=httpd", "BP_NODE_RUN_SCRIPTS=build", "BP_WEB_SERVER_ROOT=dist",
+        "BP_WEB_SERVER_ENABLE_PUSH_STATE=true"]);
+    assert_eq!(frontend.env, vec!["PORT=9000"]);
+
+    println!("The required fields of the legacy app types should be checked");
+    let fails = |preset: &str| {
+        let mut droid = droid(preset, "");
+        droid.preset.clone().unwrap().expand_into(&mut droid).unwrap_err()
+    };
+    assert_eq!(fails(r#"{"type": "node", "start_command": " "}"#), "Start Command is required for node apps");
+    assert_eq!(fails(r#"{"type": "frontend", "build_command": "", "publish_dir": "dist"}"#), "Build Command is required for frontend apps");
+    assert_eq!(fails(r#"{"type": "frontend", "build_command": "npm run build", "publish_dir": "./"}"#), "Publish directory is required for frontend apps");
+    assert_eq!(fails(r#"{"type": "static", "publish_dir": "~"}"#), "Publish directory is required for static apps");
+    assert!(fails(r#"{"type": "frontend", "build_command": "ng build --prod", "publish_dir": "dist"}"#).starts_with("Build Command must run a script"));
+    assert!(rocket::serde::json::serde_json::from_str::<Preset>(r#"{"type": "php"}"#).is_err());
+}
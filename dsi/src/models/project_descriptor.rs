@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use toml::Value;
+use crate::models::buildpack::Buildpack;
+use crate::models::droid::Droid;
+
+/// File name of the Cloud Native Buildpacks project descriptor, at the root of the repo
+pub const PROJECT_DESCRIPTOR: &str = "project.toml";
+
+/// The `project.toml` of a repo, as read by `pack build`. Both the 0.1 schema:
+///
+/// ```toml
+/// [[build.buildpacks]]
+/// id = "heroku/nodejs"
+///
+/// [[build.env]]
+/// name = "NODE_ENV"
+/// value = "production"
+/// ```
+///
+/// and the 0.2 schema (`[[io.buildpacks.group]]` and `[[io.buildpacks.build.env]]`) are understood. Everything else is
+/// left to `pack build --descriptor`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectDescriptor {
+    pub buildpacks: Vec<Buildpack>,
+    /// Build environment
+    pub env: BTreeMap<String, String>,
+    document: Value,
+}
+
+impl ProjectDescriptor {
+    /// Reads `<dir>/project.toml`, None if the repo has none
+    pub fn load(dir: &str) -> Result<Option<ProjectDescriptor>, String> {
+        let path = Path::new(dir).join(PROJECT_DESCRIPTOR);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Error reading {}: {}", PROJECT_DESCRIPTOR, e))?;
+        ProjectDescriptor::parse(&contents).map(Some).map_err(|e| format!("Invalid {}: {}", PROJECT_DESCRIPTOR, e))
+    }
+
+    pub fn parse(contents: &str) -> Result<ProjectDescriptor, String> {
+        let document: Value = toml::from_str(contents).map_err(|e| e.to_string())?;
+        let io_buildpacks = document.get("io").and_then(|io| io.get("buildpacks"));
+        let build = document.get("build");
+
+        let mut buildpacks = vec![];
+        let groups = [io_buildpacks.and_then(|t| t.get("group")), build.and_then(|t| t.get("buildpacks"))];
+        for entry in groups.iter().flatten().flat_map(|group| entries(group)) {
+            let field = |name: &str| entry.get(name).and_then(|v| v.as_str()).map(|s| s.to_string());
+            let uri = field("uri").or_else(|| field("id"))
+                .ok_or_else(|| "every buildpack needs an id or a uri".to_string())?;
+            buildpacks.push(Buildpack { uri, version: field("version"), ..Default::default() });
+        }
+
+        let mut env = BTreeMap::new();
+        let envs = [io_buildpacks.and_then(|t| t.get("build")).and_then(|t| t.get("env")), build.and_then(|t| t.get("env"))];
+        for entry in envs.iter().flatten().flat_map(|env| entries(env)) {
+            match (entry.get("name").and_then(|v| v.as_str()), entry.get("value").and_then(|v| v.as_str())) {
+                (Some(name), Some(value)) => { env.insert(name.to_string(), value.to_string()); }
+                _ => return Err("every env entry needs a name and a value".to_string()),
+            }
+        }
+        Ok(ProjectDescriptor { buildpacks, env, document })
+    }
+
+    /// Completes the droid with the descriptor: its buildpacks if the droid has none, and its build env for the keys
+    /// the droid does not set. Returns true if the buildpacks came from the descriptor, meaning that the builder has to
+    /// be created again.
+    pub fn merge_into(&self, droid: &mut Droid) -> bool {
+        let mut builder_changed = false;
+        if droid.buildpacks.is_empty() && !self.buildpacks.is_empty() {
+            droid.buildpacks = self.buildpacks.clone();
+            builder_changed = true;
+        }
+        let requested = |key: &str| droid.build_env.iter().any(|var| var.split_once('=').map(|(k, _)| k) == Some(key));
+        let defaults: Vec<String> = self.env.iter()
+            .filter(|(key, _)| !requested(key))
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        droid.build_env.extend(defaults);
+        builder_changed
+    }
+
+    /// The descriptor without its buildpacks, for `pack build` to use the ones of the builder instead
+    pub fn without_buildpacks(&self) -> String {
+        let mut document = self.document.clone();
+        if let Some(io_buildpacks) = document.get_mut("io").and_then(|io| io.get_mut("buildpacks")).and_then(|t| t.as_table_mut()) {
+            io_buildpacks.remove("group");
+        }
+        if let Some(build) = document.get_mut("build").and_then(|t| t.as_table_mut()) {
+            build.remove("buildpacks");
+        }
+        toml::to_string(&document).unwrap_or_default()
+    }
+}
+
+fn entries(value: &Value) -> Vec<&Value> {
+    value.as_array().map(|array| array.iter().collect()).unwrap_or_default()
+}
+
+#[test]
+fn test_parse() {
+    println!("Both schemas of project.toml should give their buildpacks and build env");
+    let descriptor = ProjectDescriptor::parse(r#"
+        [project]
+        id = "app"
+
+        [build]
+        exclude = ["README.md"]
+
+        [[build.buildpacks]]
+        id = "heroku/nodejs"
+        version = "0.5.0"
+
+        [[build.buildpacks]]
+        uri = "docker://heroku/procfile-cnb"
+
+        [[build.env]]
+        name = "NODE_ENV"
+        value = "production"
+    "#).unwrap();
+    assert_eq!(descriptor.buildpacks.iter().map(|b| b.uri.as_str()).collect::<Vec<_>>(), vec!["heroku/nodejs", "docker://heroku/procfile-cnb"]);
+    assert_eq!(descriptor.buildpacks[0].version.as_deref(), Some("0.5.0"));
+    assert_eq!(descriptor.env.get("NODE_ENV").map(|v| v.as_str()), Some("production"));
+    let stripped = descriptor.without_buildpacks();
+    assert!(!stripped.contains("heroku/nodejs") && stripped.contains("README.md") && stripped.contains("NODE_ENV"));
+
+    let descriptor = ProjectDescriptor::parse(r#"
+        [_]
+        schema-version = "0.2"
+
+        [[io.buildpacks.group]]
+        id = "heroku/go"
+
+        [[io.buildpacks.build.env]]
+        name = "GOVERSION"
+        value = "1.19"
+    "#).unwrap();
+    assert_eq!(descriptor.buildpacks[0].uri, "heroku/go");
+    assert_eq!(descriptor.env.get("GOVERSION").map(|v| v.as_str()), Some("1.19"));
+    assert!(!descriptor.without_buildpacks().contains("heroku/go"));
+
+    println!("The descriptor should only fill in what the request did not set");
+    let mut droid: Droid = rocket::serde::json::serde_json::from_str(r#"{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+        "buildpacks": [], "env": [], "build_env": ["GOFLAGS=-mod=vendor", "GOVERSION=1.18"]}"#).unwrap();
+    assert!(descriptor.merge_into(&mut droid));
+    assert_eq!(droid.buildpacks[0].uri, "heroku/go");
+    assert_eq!(droid.build_env, vec!["GOFLAGS=-mod=vendor", "GOVERSION=1.18"]);
+
+    assert!(ProjectDescriptor::parse("[[build.buildpacks]]\nversion = \"1\"").is_err());
+    assert!(ProjectDescriptor::parse("[[build.env]]\nname = \"A\"").is_err());
+}
@@ -0,0 +1,85 @@
+use rocket::serde::{Deserialize, Serialize};
+
+/// Resource limits of a droid container. Unset limits fall back to the operator defaults, and are unlimited if
+/// there is no default either.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct Resources {
+    /// Number of cpus, fractions allowed (i.e. 0.5)
+    pub cpu: Option<f64>,
+    /// Memory in MiB
+    pub memory: Option<u64>,
+    /// Memory plus swap in MiB, at least `memory`. Docker allows as much swap as memory if unset.
+    pub memory_swap: Option<u64>,
+    /// Maximum number of processes and threads
+    pub pids: Option<u64>,
+}
+
+const MIB: u64 = 1024 * 1024;
+
+impl Resources {
+    /// Fills the unset limits with `defaults` and checks the result against `max`
+    pub fn resolve(&self, defaults: &Resources, max: &Resources) -> Result<Resources, String> {
+        let resolved = Resources {
+            cpu: self.cpu.or(defaults.cpu),
+            memory: self.memory.or(defaults.memory),
+            memory_swap: self.memory_swap.or(defaults.memory_swap),
+            pids: self.pids.or(defaults.pids),
+        };
+        if let Some(cpu) = resolved.cpu {
+            if !(cpu > 0.0 && cpu.is_finite()) {
+                return Err(format!("cpu must be a positive number of cpus, got {}", cpu));
+            }
+        }
+        if resolved.memory == Some(0) || resolved.memory_swap == Some(0) || resolved.pids == Some(0) {
+            return Err("memory, memory_swap and pids must be positive".to_string());
+        }
+        match (resolved.memory, resolved.memory_swap) {
+            (Some(memory), Some(swap)) if swap < memory => return Err(format!("memory_swap ({} MiB) must be at least memory ({} MiB)", swap, memory)),
+            (None, Some(_)) => return Err("memory_swap requires a memory limit".to_string()),
+            _ => {}
+        }
+
+        let exceeds = |value: Option<f64>, max: Option<f64>| matches!((value, max), (Some(value), Some(max)) if value > max);
+        let over = [
+            ("cpu", exceeds(resolved.cpu, max.cpu), max.cpu.map(|m| m.to_string())),
+            ("memory", exceeds(resolved.memory.map(|m| m as f64), max.memory.map(|m| m as f64)), max.memory.map(|m| format!("{} MiB", m))),
+            ("memory_swap", exceeds(resolved.memory_swap.map(|m| m as f64), max.memory_swap.map(|m| m as f64)), max.memory_swap.map(|m| format!("{} MiB", m))),
+            ("pids", exceeds(resolved.pids.map(|p| p as f64), max.pids.map(|p| p as f64)), max.pids.map(|p| p.to_string())),
+        ];
+        match over.iter().find(|(_, exceeds, _)| *exceeds) {
+            Some((name, _, max)) => Err(format!("{} exceeds the maximum of this server ({})", name, max.clone().unwrap_or_default())),
+            None => Ok(resolved)
+        }
+    }
+
+    /// The limits in the units of the Docker Engine API: (NanoCpus, Memory, MemorySwap, PidsLimit)
+    pub fn docker_limits(&self) -> (Option<i64>, Option<i64>, Option<i64>, Option<i64>) {
+        (
+            self.cpu.map(|cpu| (cpu * 1e9) as i64),
+            self.memory.map(|memory| (memory * MIB) as i64),
+            self.memory_swap.map(|swap| (swap * MIB) as i64),
+            self.pids.map(|pids| pids as i64),
+        )
+    }
+}
+
+#[test]
+fn test_resolve() {
+    println!("Unset limits should fall back to the defaults, and limits above the maximums should be rejected");
+    let defaults = Resources { cpu: Some(1.0), memory: Some(512), ..Default::default() };
+    let max = Resources { cpu: Some(2.0), memory: Some(2048), memory_swap: None, pids: Some(500) };
+
+    let requested = Resources { memory: Some(1024), pids: Some(100), ..Default::default() };
+    assert_eq!(requested.resolve(&defaults, &max), Ok(Resources { cpu: Some(1.0), memory: Some(1024), memory_swap: None, pids: Some(100) }));
+    assert_eq!(Resources::default().resolve(&Resources::default(), &Resources::default()), Ok(Resources::default()));
+
+    assert!(Resources { cpu: Some(4.0), ..Default::default() }.resolve(&defaults, &max).unwrap_err().starts_with("cpu exceeds"));
+    assert!(Resources { pids: Some(1000), ..Default::default() }.resolve(&defaults, &max).unwrap_err().starts_with("pids exceeds"));
+    assert!(Resources { cpu: Some(-1.0), ..Default::default() }.resolve(&defaults, &max).is_err());
+    assert!(Resources { memory_swap: Some(256), ..Default::default() }.resolve(&defaults, &max).is_err());
+
+    let resolved = Resources { cpu: Some(0.5), memory: Some(256), memory_swap: Some(512), pids: Some(64) };
+    assert_eq!(resolved.docker_limits(), (Some(500_000_000), Some(256 * MIB as i64), Some(512 * MIB as i64), Some(64)));
+}
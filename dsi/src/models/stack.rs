@@ -16,15 +16,43 @@ pub struct Stack {
 // run-image = "cnbs/sample-stack-run:bionic"
 // build-image = "cnbs/sample-stack-build:bionic"
 
+/// Stacks whose images are known, so that a droid can be built on them without naming the images. Later ones are newer.
+const KNOWN_STACKS: &[(&str, &str, &str)] = &[
+    ("heroku-18", "heroku/heroku:18-cnb-build", "heroku/heroku:18-cnb"),
+    ("heroku-20", "heroku/heroku:20-cnb-build", "heroku/heroku:20-cnb"),
+    ("heroku-22", "heroku/heroku:22-cnb-build", "heroku/heroku:22-cnb"),
+    ("io.buildpacks.stacks.bionic", "paketobuildpacks/build:base-cnb", "paketobuildpacks/run:base-cnb"),
+    ("io.buildpacks.stacks.jammy", "paketobuildpacks/build-jammy-base", "paketobuildpacks/run-jammy-base"),
+];
+
 impl Stack {
+    /// The stack with its images, if it is one of the known stacks
+    pub fn known(id: &str) -> Option<Stack> {
+        KNOWN_STACKS.iter().find(|(known, _, _)| *known == id).map(|(id, build_image, run_image)| Stack {
+            id: id.to_string(),
+            build_image: build_image.to_string(),
+            run_image: run_image.to_string(),
+        })
+    }
+
+    /// The newest known stack among `stacks` (a `*` standing for any stack) that is also in `allowed`, unless
+    /// `allowed` is empty
+    pub fn newest_known(stacks: &[String], allowed: &[String]) -> Option<Stack> {
+        KNOWN_STACKS.iter().rev()
+            .map(|(id, _, _)| id.to_string())
+            .filter(|id| stacks.iter().any(|stack| stack == "*" || stack == id))
+            .find(|id| allowed.is_empty() || allowed.contains(id))
+            .and_then(|id| Stack::known(&id))
+    }
+
     /// Detects the common stacks for the buildpacks in the provided buildpacks vector.
     /// NOTE: If the buildpacks are not validated (i.e. the version and compatible stacks are not set), they will be validated here.
-    pub async fn detect_common_stacks(buildpack_list: &mut Vec<Buildpack>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    pub async fn detect_common_stacks(registry_url: &str, buildpack_list: &mut [Buildpack]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
         let mut common_stacks = Vec::new();
 
         'buildpacks: for (i, bp) in buildpack_list.iter_mut().enumerate() {
             if bp.version.is_none() || bp.compatible_stacks.is_none() {
-                bp.validate().await?;
+                bp.validate(registry_url).await?;
             }
             let compatible_stacks = match &bp.compatible_stacks {
                 Some(stacks) => stacks,
@@ -48,7 +76,7 @@ impl Stack {
                     if stack == "*" {
                         continue 'buildpacks;
                     }
-                    if common_stacks.contains(&stack) {
+                    if common_stacks.contains(stack) {
                         matching_stacks.push(stack.clone());
                     }
                 }
@@ -76,9 +104,35 @@ fn test_detect_common_stacks() {
         Buildpack::from_uri("heroku/nodejs").unwrap(),
         Buildpack::from_uri("heroku/ruby").unwrap(),
     ];
-    if let Ok(stacks) = tokio_test::block_on(Stack::detect_common_stacks(&mut buildpacks)) {
-        assert!(stacks.contains(&"heroku-18".to_string()));
-        assert!(stacks.contains(&"heroku-20".to_string()));
-    }
-    // assert_eq!(common_stacks, vec!["heroku-18", "heroku-20"]);
-}
\ No newline at end of file
+    let stacks = tokio_test::block_on(Stack::detect_common_stacks(crate::test_support::registry_url(), &mut buildpacks)).unwrap();
+    assert_eq!(stacks, vec!["heroku-18", "heroku-20"]);
+    assert_eq!(buildpacks[0].version, Some("0.5.0".to_string()));
+}
+
+#[test]
+fn test_newest_known() {
+    println!("The newest known stack allowed on the server should be chosen");
+    let stacks = vec!["heroku-18".to_string(), "heroku-20".to_string(), "custom".to_string()];
+    assert_eq!(Stack::newest_known(&stacks, &[]).unwrap().build_image, "heroku/heroku:20-cnb-build");
+    assert_eq!(Stack::newest_known(&stacks, &["heroku-18".to_string()]).unwrap().id, "heroku-18");
+    assert_eq!(Stack::newest_known(&["*".to_string()], &[]).unwrap().id, "io.buildpacks.stacks.jammy");
+    assert!(Stack::newest_known(&["custom".to_string()], &[]).is_none());
+}
+
+#[test]
+fn test_detect_common_stacks_errors() {
+    let registry = crate::test_support::registry_url();
+
+    println!("Buildpacks without any stack in common should fail");
+    let mut buildpacks = vec![
+        Buildpack::from_uri("heroku/nodejs").unwrap(),
+        Buildpack::from_uri("paketo-buildpacks/go").unwrap(),
+    ];
+    let error = tokio_test::block_on(Stack::detect_common_stacks(registry, &mut buildpacks)).unwrap_err();
+    assert_eq!(error.to_string(), "No common stacks found for buildpack paketo-buildpacks/go");
+
+    println!("A buildpack without versions in the registry should fail");
+    let mut buildpacks = vec![Buildpack::from_uri("heroku/empty").unwrap()];
+    let error = tokio_test::block_on(Stack::detect_common_stacks(registry, &mut buildpacks)).unwrap_err();
+    assert_eq!(error.to_string(), "No versions found");
+}
@@ -1,103 +1,641 @@
-use std::io;
-use std::io::{Error, ErrorKind};
-use rocket::serde::json::{Json};
-use rocket::response::stream::ReaderStream;
-use rocket::tokio::io::BufReader;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::json::serde_json::json;
+use rocket::response::stream::EventStream;
+use rocket::State;
+use rocket::data::{Data, ToByteUnit};
+use crate::config::DsiConfig;
 use crate::models::builder::Builder;
 use crate::models::droid::Droid;
+use crate::models::droid_state::{DroidState, DroidStatus};
+use crate::models::env::{self, EnvVars};
+use crate::models::git_credentials::{GitCredentials, GitToken, TOKEN_USERNAME};
+use crate::models::manifest::AppConfig;
+use crate::utility::archive::{self, ArchiveFormat};
+use crate::utility::build_log::{BuildLogs, LastEventId};
+use crate::utility::docker::DockerClient;
+use crate::utility::nginx::Nginx;
+use crate::utility::pipeline::{Pipeline, Source};
+use crate::utility::router_client::RouterClient;
+use crate::utility::secrets::{SecretBox, REDACTED};
+use crate::utility::snooze::reroute;
+use crate::utility::store::DroidStore;
 
+/// Builds and deploys the droid in the background, streaming the build events as server-sent events.
 #[post("/", data = "<droid>")]
-pub async fn new(mut droid: Json<Droid>) // -> status::Custom<Value> { // returns a chained stream of stdout and stderr
-                                            -> io::Result<ReaderStream![BufReader<tokio::process::ChildStdout>]> {
-    match droid.detect_common_stacks().await {
-        Ok(common_stacks) => {
-            println!("Common stacks detected: {:?}", common_stacks);
-            // if !common_stacks.contains(&droid.stack.id) {
-            //     return status::Custom(Status::BadRequest, json!({
-            //         "message": "The stack provided is not compatible with the buildpacks provided",
-            //         "data": {
-            //             "compatible_stacks": common_stacks
-            //         }
-            //     }));
-            // }
-        }
-        Err(e) => {
-            println!("Error: {:?}", e);
-            // return status::Custom(Status::BadRequest, json!({
-            //     "message": "Error while detecting common stacks",
-            //     "data": {
-            //         "error": e.to_string()
-            //     }
-            // }));
+pub async fn new(mut droid: Json<Droid>, store: &State<DroidStore>, pipeline: &State<Pipeline>,
+                 logs: &State<BuildLogs>, config: &State<DsiConfig>) // -> status::Custom<Value> {
+                 -> Result<EventStream![], status::Custom<Value>> {
+    // marks the droid as being built right away, so that a concurrent deploy of the same droid gets a 409
+    let build = pipeline.builds.start(droid.app_id).ok_or_else(being_built)?;
+    droid.root_dir = match droid.root_dir() {
+        Ok(root_dir) => root_dir,
+        Err(err) => return Err(status::Custom(Status::BadRequest, json!({
+            "message": "Invalid root_dir",
+            "error": err,
+            "data": {}
+        })))
+    };
+    droid.commit = match droid.commit() {
+        Ok(commit) => commit,
+        Err(err) => return Err(status::Custom(Status::BadRequest, json!({
+            "message": "Invalid commit",
+            "error": err,
+            "data": {}
+        })))
+    };
+    if let Some(preset) = droid.preset.clone() {
+        if let Err(err) = preset.expand_into(&mut droid) {
+            return Err(status::Custom(Status::BadRequest, json!({
+                "message": "Invalid preset",
+                "error": err,
+                "data": {}
+            })));
         }
+    }
+    let resources = match droid.resources.resolve(&config.default_resources, &config.max_resources) {
+        Ok(resources) => resources,
+        Err(err) => return Err(status::Custom(Status::BadRequest, json!({
+            "message": "Invalid resource limits",
+            "error": err,
+            "data": {}
+        })))
     };
+    let (env, build_env, port) = match droid.parse_env(config.default_port) {
+        Ok(parsed) => parsed,
+        Err(err) => return Err(status::Custom(Status::BadRequest, json!({
+            "message": "Invalid environment",
+            "error": err,
+            "data": {}
+        })))
+    };
+    droid.build_env = env::to_list(&build_env);
+
+    // droids without buildpacks get them from their repo, the build detects their stacks
+    if !droid.buildpacks.is_empty() {
+        match droid.detect_common_stacks(&config.registry_url).await {
+            Ok(common_stacks) => {
+                println!("Common stacks detected: {:?}", common_stacks);
+                // if !common_stacks.contains(&droid.stack.id) {
+                //     return status::Custom(Status::BadRequest, json!({
+                //         "message": "The stack provided is not compatible with the buildpacks provided",
+                //         "data": {
+                //             "compatible_stacks": common_stacks
+                //         }
+                //     }));
+                // }
+            }
+            Err(e) => return Err(status::Custom(Status::BadRequest, json!({
+                "message": "Error while detecting common stacks",
+                "error": e.to_string(),
+                "data": {}
+            })))
+        }
+    }
 
     let builder: Builder = match droid.create_builder().await {
-        Ok(builder) => {
-            println!("Saving Builder: {:?}", builder);
-            match builder.save(droid.app_id.to_string()) {
-                Ok(path) => {
-                    println!("Builder dumped to file: {:?}", path);
-                }
-                Err(e) => {
-                    println!("Error: {:?}", e);
-                    // return status::Custom(Status::BadRequest, json!({
-                    //     "message": "Error while dumping builder to file",
-                    //     "data": {
-                    //         "error": e.to_string()
-                    //     }
-                    // }));
-                }
+        Ok(builder) => builder,
+        Err(error) => return Err(status::Custom(Status::BadRequest, json!({
+            "message": "Error while creating builder",
+            "error": error,
+            "data": {}
+        })))
+    };
+    // the builder of a droid without buildpacks is saved by the build, once they are known
+    if !droid.buildpacks.is_empty() {
+        println!("Saving Builder: {:?}", builder);
+        match builder.save(store.dumps_dir(), droid.app_id.to_string()) {
+            Ok(path) => println!("Builder dumped to file: {:?}", path),
+            Err(e) => return Err(status::Custom(Status::InternalServerError, json!({
+                "message": "Error while dumping builder to file",
+                "error": e.to_string(),
+                "data": {}
+            })))
+        }
+    }
+
+    // a redeployed droid keeps its uid, and so its addresses, and its last build and container until it is built
+    // and deployed again
+    let previous = store.get(droid.app_id);
+    let uid = previous.as_ref().map(|s| s.uid.clone()).unwrap_or_else(|| store.new_uid());
+    let mut state = DroidState::new(droid.app_id, &uid);
+    if let Some(previous) = previous {
+        state.container = previous.container;
+        state.image = previous.image;
+        state.source = previous.source;
+        state.build_digest = previous.build_digest;
+    }
+    state.builder = Some(builder.image(droid.app_id));
+    state.port = Some(port);
+    state.secrets = droid.secrets.iter().cloned().collect();
+    state.env = env;
+    for key in &state.secrets {
+        if let Some(value) = state.env.get_mut(key) {
+            *value = pipeline.secrets.encrypt(value);
+        }
+    }
+    state.build_env = build_env;
+    state.name = droid.name.clone();
+    state.resources = resources;
+    state.config = AppConfig::of(&droid, None);
+    if let Err(e) = store.save(state) {
+        println!("Error saving droid state: {:?}", e);
+    }
+
+    let log = logs.start(droid.app_id);
+    let pipeline = pipeline.inner().clone();
+    let events = log.clone().subscribe(0);
+    tokio::spawn(async move { pipeline.run(droid.into_inner(), builder, Source::Git, log, build).await });
+
+    Ok(events)
+}
+
+/// Builds and deploys the droid from an uploaded `.tar.gz` or `.zip` of its source instead of its repo, with the
+/// config of its last build. Streams the build events like `POST /droids`.
+#[post("/<app_id>/source", data = "<archive>")]
+pub async fn upload_source(app_id: i64, archive: Data<'_>, store: &State<DroidStore>, pipeline: &State<Pipeline>,
+                           logs: &State<BuildLogs>, config: &State<DsiConfig>) -> Result<EventStream![], status::Custom<Value>> {
+    let state = match store.get(app_id) {
+        Some(state) => state,
+        None => return Err(status::Custom(Status::NotFound, json!({
+            "message": "Droid not found",
+            "data": {}
+        })))
+    };
+    let build = pipeline.builds.start(app_id).ok_or_else(being_built)?;
+    let builder = Builder::load(store.dumps_dir(), app_id).map_err(|e| upload_error(Status::NotFound, "Droid has no builder", e.to_string()))?;
+    let env = pipeline.secrets.open(&state.env, &state.secrets).map_err(|e| upload_error(Status::InternalServerError, "Error opening the secrets", e))?;
+
+    let path = format!("{}/{}/source.archive", store.dumps_dir(), app_id);
+    let written = archive.open(config.max_upload_size.mebibytes()).into_file(&path).await
+        .map_err(|e| upload_error(Status::InternalServerError, "Error saving the archive", e.to_string()))?;
+    if !written.is_complete() {
+        let _ = std::fs::remove_file(&path);
+        return Err(upload_error(Status::PayloadTooLarge, "Archive too large",
+                                format!("The archive exceeds the maximum of {} MiB", config.max_upload_size)));
+    }
+    let mut head = [0u8; 4];
+    let read = std::fs::File::open(&path).and_then(|mut file| file.read(&mut head)).unwrap_or(0);
+    let format = match ArchiveFormat::detect(&head[..read]) {
+        Some(format) => format,
+        None => {
+            let _ = std::fs::remove_file(&path);
+            return Err(upload_error(Status::UnsupportedMediaType, "Unsupported archive",
+                                    "The source must be a .tar.gz or a .zip".to_string()));
+        }
+    };
+    let digest = archive::digest(&path).map_err(|e| upload_error(Status::InternalServerError, "Error reading the archive", e.to_string()))?;
+    println!("Droid {}: building from an uploaded archive ({})", app_id, digest);
+
+    let droid = Droid {
+        app_id,
+        name: state.name.clone(),
+        repo: String::new(),
+        branch: String::new(),
+        commit: None,
+        root_dir: state.config.root_dir.clone(),
+        buildpacks: builder.buildpacks.clone(),
+        preset: None,
+        env: env::to_list(&env),
+        secrets: state.secrets.iter().cloned().collect(),
+        build_env: env::to_list(&state.build_env),
+        resources: state.resources.clone(),
+        stack: builder.stack.clone(),
+    };
+    store.update(app_id, |s| s.status = DroidStatus::Building);
+    let log = logs.start(app_id);
+    let pipeline = pipeline.inner().clone();
+    let events = log.clone().subscribe(0);
+    tokio::spawn(async move { pipeline.run(droid, builder, Source::Archive { path, format, digest }, log, build).await });
+
+    Ok(events)
+}
+
+fn being_built() -> status::Custom<Value> {
+    status::Custom(Status::Conflict, json!({
+        "message": "The droid is being built",
+        "data": {}
+    }))
+}
+
+fn upload_error(status: Status, message: &str, err: String) -> status::Custom<Value> {
+    println!("Error: {}", err);
+    status::Custom(status, json!({
+        "message": message,
+        "error": err,
+        "data": {}
+    }))
+}
+
+/// Returns the name of the droid container, or a 404 response if the droid has none.
+fn container_of(store: &DroidStore, app_id: i64) -> Result<String, status::Custom<Value>> {
+    match store.get(app_id).and_then(|s| s.container) {
+        Some(container) => Ok(container),
+        None => Err(status::Custom(Status::NotFound, json!({
+            "message": "Droid has no container",
+            "data": {}
+        })))
+    }
+}
+
+fn docker_error(err: impl Into<String>) -> status::Custom<Value> {
+    let err = err.into();
+    println!("Error: {}", err);
+    status::Custom(Status::InternalServerError, json!({
+        "message": "Docker operation failed",
+        "error": err,
+        "data": {}
+    }))
+}
+
+/// The config the droid runs with: its `AppConfig`, port, resource limits and redacted environment
+fn effective_config(state: &DroidState) -> Value {
+    let mut config = json!(state.config);
+    config["port"] = json!(state.port);
+    config["resources"] = json!(state.resources);
+    config["env"] = json!(state.redacted().env);
+    config
+}
+
+/// Returns the recorded state of the droid, the addresses it is served at, and the state of its container if it has one.
+/// `oom_killed` tells whether the container was last stopped for going over its memory limit.
+#[get("/<app_id>")]
+pub async fn get(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>, config: &State<DsiConfig>) -> status::Custom<Value> {
+    let state = match store.get(app_id) {
+        Some(state) => state,
+        None => return status::Custom(Status::NotFound, json!({
+            "message": "Droid not found",
+            "data": {}
+        }))
+    };
+    let container = match &state.container {
+        Some(container) => match docker.inspect_container(container).await {
+            Ok(inspect) => Some(inspect.state),
+            Err(err) => {
+                println!("Error: {}", err);
+                None
+            }
+        },
+        None => None
+    };
+    status::Custom(Status::Ok, json!({
+        "message": "Droid status",
+        "data": {
+            "addresses": state.addresses(&config.server_id, &config.domains).iter().map(|a| a.to_string()).collect::<Vec<String>>(),
+            "oom_killed": container.as_ref().is_some_and(|c| c.oom_killed),
+            "config": effective_config(&state),
+            "droid": state.redacted(),
+            "container": container
+        }
+    }))
+}
+
+/// Streams the events of the last build of the droid. A client reconnecting with a `Last-Event-ID` header
+/// resumes after that event.
+#[get("/<app_id>/build")]
+pub async fn build_events(app_id: i64, last_event_id: LastEventId, logs: &State<BuildLogs>) -> Result<EventStream![], status::Custom<Value>> {
+    match logs.get(app_id) {
+        Some(log) => Ok(log.subscribe(last_event_id.0)),
+        None => Err(status::Custom(Status::NotFound, json!({
+            "message": "No build found for droid",
+            "data": {}
+        })))
+    }
+}
+
+/// Returns the last `tail` lines (100 by default) of the droid logs.
+#[get("/<app_id>/logs?<tail>")]
+pub async fn logs(app_id: i64, tail: Option<usize>, store: &State<DroidStore>, docker: &State<DockerClient>) -> status::Custom<Value> {
+    let container = match container_of(store, app_id) {
+        Ok(container) => container,
+        Err(response) => return response
+    };
+    match docker.logs(&container, tail.unwrap_or(100)).await {
+        Ok(lines) => status::Custom(Status::Ok, json!({
+            "message": "Droid logs",
+            "data": {
+                "logs": lines
+            }
+        })),
+        Err(err) => docker_error(err)
+    }
+}
+
+/// Resource usage of the droid container: cpu (in percent of one cpu), memory, network I/O since the container
+/// started, and uptime (null if the container is not running).
+#[get("/<app_id>/stats")]
+pub async fn stats(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>) -> status::Custom<Value> {
+    let container = match container_of(store, app_id) {
+        Ok(container) => container,
+        Err(response) => return response
+    };
+    let (stats, inspect) = match tokio::try_join!(docker.stats(&container), docker.inspect_container(&container)) {
+        Ok(result) => result,
+        Err(err) => return docker_error(err)
+    };
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let uptime = match inspect.state.running {
+        true => crate::utility::docker::parse_timestamp(&inspect.state.started_at).map(|started| now.saturating_sub(started)),
+        false => None
+    };
+    let network = stats.network_totals();
+    status::Custom(Status::Ok, json!({
+        "message": "Droid stats",
+        "data": {
+            "cpu_percent": stats.cpu_percent(),
+            "memory": {
+                "usage": stats.memory_stats.usage,
+                "limit": stats.memory_stats.limit
+            },
+            "network": {
+                "rx_bytes": network.rx_bytes,
+                "tx_bytes": network.tx_bytes
+            },
+            "uptime_secs": uptime
+        }
+    }))
+}
+
+fn env_response(message: &str, state: &DroidState) -> status::Custom<Value> {
+    status::Custom(Status::Ok, json!({
+        "message": message,
+        "data": {
+            "env": state.redacted().env,
+            "secrets": state.secrets,
+            "build_env": state.build_env
+        }
+    }))
+}
+
+/// Returns the runtime environment of the droid with its secrets redacted, and the environment of its last build.
+#[get("/<app_id>/env")]
+pub fn get_env(app_id: i64, store: &State<DroidStore>) -> status::Custom<Value> {
+    match store.get(app_id) {
+        Some(state) => env_response("Droid environment", &state),
+        None => status::Custom(Status::NotFound, json!({
+            "message": "Droid not found",
+            "data": {}
+        }))
+    }
+}
+
+/// Encrypts the secrets of `env` whose value changed. Secrets sent back redacted, or unchanged, keep their
+/// recorded value.
+fn seal_secrets(env: &mut EnvVars, secrets: &BTreeSet<String>, previous: &DroidState, secret_box: &SecretBox) -> Result<(), String> {
+    for key in secrets {
+        let value = match env.get_mut(key) {
+            Some(value) => value,
+            None => continue
+        };
+        let recorded = previous.env.get(key).filter(|_| previous.secrets.contains(key));
+        match recorded {
+            Some(recorded) if value == REDACTED || value == recorded => *value = recorded.clone(),
+            _ if value == REDACTED => return Err(format!("{} has no recorded value", key)),
+            _ => *value = secret_box.encrypt(value),
+        }
+    }
+    Ok(())
+}
+
+/// Validates the new runtime environment of the droid, records it and recreates the container from the current
+/// image. The keys in `secret` are marked as secrets. The build environment only changes with a new build.
+async fn update_env(app_id: i64, update: impl FnOnce(&mut EnvVars), secret: Vec<String>, store: &DroidStore,
+                    pipeline: &Pipeline, config: &DsiConfig) -> status::Custom<Value> {
+    let state = match store.get(app_id) {
+        Some(state) => state,
+        None => return status::Custom(Status::NotFound, json!({
+            "message": "Droid not found",
+            "data": {}
+        }))
+    };
+    if pipeline.builds.contains(app_id) {
+        return being_built();
+    }
+    let mut env = state.env.clone();
+    update(&mut env);
+    let secrets: BTreeSet<String> = state.secrets.iter().filter(|key| env.contains_key(*key)).cloned().chain(secret).collect();
+    let result = env.keys().try_for_each(|key| env::check_key(key))
+        .and_then(|_| env::with_port(&mut env, config.default_port))
+        .and_then(|port| secrets.iter().try_for_each(|key| env::check_secret(key, &env)).map(|_| port))
+        .and_then(|port| seal_secrets(&mut env, &secrets, &state, &pipeline.secrets).map(|_| port));
+    let port = match result {
+        Ok(port) => port,
+        Err(err) => return status::Custom(Status::BadRequest, json!({
+            "message": "Invalid environment",
+            "error": err,
+            "data": {}
+        }))
+    };
+    store.update(app_id, |s| {
+        s.env = env;
+        s.secrets = secrets;
+        s.port = Some(port);
+    });
+    if let Err(err) = pipeline.apply_env(app_id, state.port).await {
+        return docker_error(err);
+    }
+    env_response("Droid environment updated", &store.get(app_id).unwrap_or(state))
+}
+
+/// Replaces the runtime environment of the droid. PORT is added back if missing.
+#[put("/<app_id>/env?<secret>", data = "<env>")]
+pub async fn put_env(app_id: i64, secret: Vec<String>, env: Json<EnvVars>, store: &State<DroidStore>,
+                     pipeline: &State<Pipeline>, config: &State<DsiConfig>) -> status::Custom<Value> {
+    update_env(app_id, |current| *current = env.into_inner(), secret, store, pipeline, config).await
+}
+
+/// Sets the given variables of the runtime environment, and removes the ones set to null.
+#[patch("/<app_id>/env?<secret>", data = "<changes>")]
+pub async fn patch_env(app_id: i64, secret: Vec<String>, changes: Json<BTreeMap<String, Option<String>>>,
+                       store: &State<DroidStore>, pipeline: &State<Pipeline>, config: &State<DsiConfig>) -> status::Custom<Value> {
+    update_env(app_id, |current| {
+        for (key, value) in changes.into_inner() {
+            match value {
+                Some(value) => current.insert(key, value),
+                None => current.remove(&key),
             };
-            builder
         }
-        Err(error) => {
-            panic!("Error: {:?}", error);
-            // println!("Error: {:?}", error);
-            // return status::Custom(Status::BadRequest, json!({
-            //     "message": "Error while creating builder",
-            //     "data": {
-            //         "error": error.to_string()
-            //     }
-            // }));
+    }, secret, store, pipeline, config).await
+}
+
+fn credentials_response(message: &str, credentials: &GitCredentials) -> status::Custom<Value> {
+    status::Custom(Status::Ok, json!({
+        "message": message,
+        "data": credentials.redacted()
+    }))
+}
+
+fn credentials_error(message: &str, err: String) -> status::Custom<Value> {
+    println!("Error: {}", err);
+    status::Custom(Status::InternalServerError, json!({
+        "message": message,
+        "error": err,
+        "data": {}
+    }))
+}
+
+/// Generates an SSH deploy key the droid clones its repo with, replacing its git credentials. The public key is
+/// returned to be added to the repo. Credentials can be set before the droid is first deployed.
+#[post("/<app_id>/deploy-key")]
+pub async fn deploy_key(app_id: i64, store: &State<DroidStore>, pipeline: &State<Pipeline>) -> status::Custom<Value> {
+    let credentials = match GitCredentials::generate(pipeline.runner.as_ref(), store.dumps_dir(), app_id, &pipeline.secrets).await {
+        Ok(credentials) => credentials,
+        Err(err) => return credentials_error("Error generating the deploy key", err)
+    };
+    if let Err(err) = credentials.save(store.dumps_dir(), app_id) {
+        return credentials_error("Error saving the git credentials", err);
+    }
+    credentials_response("Deploy key generated", &credentials)
+}
+
+/// Sets the HTTPS token the droid clones its repo with, replacing its git credentials
+#[put("/<app_id>/git-token", data = "<token>")]
+pub async fn put_git_token(app_id: i64, token: Json<GitToken>, store: &State<DroidStore>, pipeline: &State<Pipeline>) -> status::Custom<Value> {
+    if token.token.trim().is_empty() {
+        return status::Custom(Status::BadRequest, json!({
+            "message": "Invalid git token",
+            "error": "The token is empty",
+            "data": {}
+        }));
+    }
+    let credentials = GitCredentials::Token {
+        username: token.username.clone().filter(|u| !u.trim().is_empty()).unwrap_or_else(|| TOKEN_USERNAME.to_string()),
+        token: pipeline.secrets.encrypt(token.token.trim()),
+    };
+    if let Err(err) = credentials.save(store.dumps_dir(), app_id) {
+        return credentials_error("Error saving the git credentials", err);
+    }
+    credentials_response("Git token saved", &credentials)
+}
+
+/// The git credentials of the droid, without their secret halves
+#[get("/<app_id>/git-credentials")]
+pub async fn get_git_credentials(app_id: i64, store: &State<DroidStore>) -> status::Custom<Value> {
+    match GitCredentials::load(store.dumps_dir(), app_id) {
+        Ok(Some(credentials)) => credentials_response("Droid git credentials", &credentials),
+        Ok(None) => status::Custom(Status::NotFound, json!({
+            "message": "Droid has no git credentials",
+            "data": {}
+        })),
+        Err(err) => credentials_error("Error reading the git credentials", err)
+    }
+}
+
+/// Removes the git credentials of the droid, its repo being cloned anonymously again
+#[delete("/<app_id>/git-credentials")]
+pub async fn delete_git_credentials(app_id: i64, store: &State<DroidStore>) -> status::Custom<Value> {
+    match GitCredentials::remove(store.dumps_dir(), app_id) {
+        Ok(true) => status::Custom(Status::Ok, json!({ "message": "Git credentials removed", "data": {} })),
+        Ok(false) => status::Custom(Status::NotFound, json!({
+            "message": "Droid has no git credentials",
+            "data": {}
+        })),
+        Err(err) => credentials_error("Error removing the git credentials", err)
+    }
+}
+
+#[post("/<app_id>/start")]
+pub async fn start(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>, nginx: &State<Nginx>) -> status::Custom<Value> {
+    let container = match container_of(store, app_id) {
+        Ok(container) => container,
+        Err(response) => return response
+    };
+    match docker.start_container(&container).await {
+        Ok(()) => {
+            store.update(app_id, |s| s.status = DroidStatus::Running);
+            reroute(nginx, store).await;
+            status::Custom(Status::Ok, json!({ "message": "Droid started", "data": {} }))
+        }
+        Err(err) => docker_error(err)
+    }
+}
+
+#[post("/<app_id>/stop")]
+pub async fn stop(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>, nginx: &State<Nginx>) -> status::Custom<Value> {
+    let container = match container_of(store, app_id) {
+        Ok(container) => container,
+        Err(response) => return response
+    };
+    match docker.stop_container(&container).await {
+        Ok(()) => {
+            store.update(app_id, |s| s.status = DroidStatus::Stopped);
+            reroute(nginx, store).await;
+            status::Custom(Status::Ok, json!({ "message": "Droid stopped", "data": {} }))
+        }
+        Err(err) => docker_error(err)
+    }
+}
+
+#[post("/<app_id>/restart")]
+pub async fn restart(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>) -> status::Custom<Value> {
+    let container = match container_of(store, app_id) {
+        Ok(container) => container,
+        Err(response) => return response
+    };
+    match docker.restart_container(&container).await {
+        Ok(()) => {
+            store.update(app_id, |s| s.status = DroidStatus::Running);
+            status::Custom(Status::Ok, json!({ "message": "Droid restarted", "data": {} }))
         }
+        Err(err) => docker_error(err)
+    }
+}
+
+/// Stops the droid container, the droid keeps its route and is woken up by the next request.
+#[post("/<app_id>/snooze")]
+pub async fn snooze(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>, nginx: &State<Nginx>) -> status::Custom<Value> {
+    let container = match container_of(store, app_id) {
+        Ok(container) => container,
+        Err(response) => return response
     };
+    match crate::utility::snooze::snooze(store, docker, nginx, app_id, &container).await {
+        Ok(()) => status::Custom(Status::Ok, json!({ "message": "Droid snoozed", "data": {} })),
+        Err(err) => docker_error(err)
+    }
+}
 
-    // match builder.run_create(droid.app_id).await {
-    //     Ok(child) => unsafe {
-    //         let child_id = child.id();
-    //         crate::tasklist::CHILDREN.push(child);
-    //         println!("Droid created");
-    //         status::Custom(Status::Ok, json!({
-    //             "message": "Droid created",
-    //             "data": {
-    //                 "id": child_id
-    //             }
-    //         }))
-    //     }
-    //     Err(error) => {
-    //         println!("Error: {:?}", error);
-    //         status::Custom(Status::BadRequest, json!({
-    //             "message": "Error while creating droid",
-    //             "data": {
-    //                 "error": error.to_string()
-    //             }
-    //         }))
-    //     }
-    // }
-
-    match builder.run_create(droid.app_id).await {
-        Ok(mut child) => {
-            let stdout = child.stdout
-                .take()
-                .ok_or_else(|| Error::new(ErrorKind::Other,"Could not capture standard output."))?;
-
-            let stdout = BufReader::new(stdout);
-            Ok(ReaderStream::one(stdout))
+#[post("/<app_id>/wake")]
+pub async fn wake(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>, nginx: &State<Nginx>) -> status::Custom<Value> {
+    let container = match container_of(store, app_id) {
+        Ok(container) => container,
+        Err(response) => return response
+    };
+    match crate::utility::snooze::wake(store, docker, nginx, app_id, &container).await {
+        Ok(()) => status::Custom(Status::Ok, json!({ "message": "Droid woken up", "data": {} })),
+        Err(err) => docker_error(err)
+    }
+}
+
+/// Removes the droid container, its images and its dumps, and stops routing to it.
+#[delete("/<app_id>")]
+pub async fn delete(app_id: i64, store: &State<DroidStore>, docker: &State<DockerClient>, nginx: &State<Nginx>,
+                    router: &State<RouterClient>) -> status::Custom<Value> {
+    let state = match store.get(app_id) {
+        Some(state) => state,
+        None => return status::Custom(Status::NotFound, json!({
+            "message": "Droid not found",
+            "data": {}
+        }))
+    };
+    if let Some(container) = &state.container {
+        if let Err(err) = docker.remove_container(container).await {
+            if !err.is_not_found() {
+                return docker_error(err);
+            }
         }
-        Err(error) => {
-            println!("Error: {:?}", error);
-            Err(error)
+    }
+    for image in state.image.iter().chain(state.builder.iter()) {
+        if let Err(err) = docker.remove_image(image).await {
+            println!("Error removing image {}: {}", image, err);
         }
     }
+    if let Err(e) = store.remove(app_id) {
+        println!("Error removing dumps of droid {}: {}", app_id, e);
+    }
+    reroute(nginx, store).await;
+    router.report("deleted", &state).await;
+    status::Custom(Status::Ok, json!({ "message": "Droid deleted", "data": {} }))
 }
@@ -1,2 +1,4 @@
 pub mod droids_router;
-pub mod stacks_router;
\ No newline at end of file
+pub mod node_router;
+pub mod stacks_router;
+pub mod reconcile_router;
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+use rocket::serde::json::Value;
+use rocket::serde::json::serde_json::json;
+use rocket::State;
+use command_runner::CommandRunner;
+use crate::config::DsiConfig;
+use crate::models::droid_state::DroidStatus;
+use crate::utility::host;
+use crate::utility::store::DroidStore;
+
+/// Total and free resources of the droid-server, and how many droids it runs. The disk is the one holding the dumps
+/// directory, it is null if `df` fails.
+#[get("/capacity")]
+pub async fn capacity(store: &State<DroidStore>, runner: &State<Arc<dyn CommandRunner>>, config: &State<DsiConfig>) -> Value {
+    let states = store.all();
+    let count = |status: DroidStatus| states.iter().filter(|state| state.status == status).count();
+    let capacity = host::capacity(states.len());
+    let disk = match host::disk(runner.as_ref(), &config.dumps_dir).await {
+        Ok((total, free)) => json!({ "total": total, "free": free }),
+        Err(err) => {
+            println!("Error: {}", err);
+            Value::Null
+        }
+    };
+    json!({
+        "message": "Node capacity",
+        "data": {
+            "cpu": {
+                "total": capacity.cpus,
+                "free": capacity.cpus as f64 * (1.0 - capacity.cpu_usage),
+                "usage": capacity.cpu_usage
+            },
+            "memory": {
+                "total": capacity.memory_total,
+                "free": capacity.memory_free
+            },
+            "disk": disk,
+            "droids": {
+                "total": states.len(),
+                "running": count(DroidStatus::Running),
+                "snoozed": count(DroidStatus::Snoozed)
+            }
+        }
+    })
+}
@@ -0,0 +1,53 @@
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Value;
+use rocket::serde::json::serde_json::json;
+use std::sync::Arc;
+use rocket::State;
+use command_runner::CommandRunner;
+use crate::config::DsiConfig;
+use crate::utility::docker::DockerClient;
+use crate::utility::pipeline::Pipeline;
+use crate::utility::reconciler;
+use crate::utility::store::DroidStore;
+
+/// Dry run: shows what the reconciler would change without changing anything.
+#[get("/")]
+pub async fn plan(store: &State<DroidStore>, docker: &State<DockerClient>, pipeline: &State<Pipeline>) -> status::Custom<Value> {
+    match reconciler::observe(store, docker, &pipeline.builds).await {
+        Ok(observed) => status::Custom(Status::Ok, json!({
+            "message": "Reconciliation plan",
+            "data": {
+                "actions": reconciler::plan(&store.all(), &observed)
+            }
+        })),
+        Err(err) => status::Custom(Status::InternalServerError, json!({
+            "message": "Reconciliation failed",
+            "error": err,
+            "data": {}
+        }))
+    }
+}
+
+#[post("/")]
+pub async fn run(store: &State<DroidStore>, docker: &State<DockerClient>, runner: &State<Arc<dyn CommandRunner>>,
+                 config: &State<DsiConfig>, pipeline: &State<Pipeline>) -> status::Custom<Value> {
+    match reconciler::observe(store, docker, &pipeline.builds).await {
+        Ok(observed) => {
+            let actions = reconciler::plan(&store.all(), &observed);
+            reconciler::apply(store, docker, runner.as_ref(), &actions, config.remove_orphans).await;
+            status::Custom(Status::Ok, json!({
+                "message": "Reconciliation applied",
+                "data": {
+                    "actions": actions,
+                    "orphans_removed": config.remove_orphans
+                }
+            }))
+        }
+        Err(err) => status::Custom(Status::InternalServerError, json!({
+            "message": "Reconciliation failed",
+            "error": err,
+            "data": {}
+        }))
+    }
+}
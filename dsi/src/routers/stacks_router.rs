@@ -2,12 +2,14 @@ use rocket::http::Status;
 use rocket::response::status;
 use rocket::serde::json::{Json, Value};
 use rocket::serde::json::serde_json::json;
+use rocket::State;
+use crate::config::DsiConfig;
 use crate::models::buildpack::Buildpack;
 use crate::models::stack::Stack;
 
 #[post("/suggest", data = "<buildpacks>")]
-pub async fn common(mut buildpacks: Json<Vec<Buildpack>>) -> status::Custom<Value> {
-    match Stack::detect_common_stacks(&mut buildpacks).await {
+pub async fn common(mut buildpacks: Json<Vec<Buildpack>>, config: &State<DsiConfig>) -> status::Custom<Value> {
+    match Stack::detect_common_stacks(&config.registry_url, &mut buildpacks).await {
         Ok(common_stacks) => status::Custom(Status::Ok, json!({
                 "message": "Common stacks detected",
                 "data": {
@@ -0,0 +1,371 @@
+//! Fakes for the services the DSI talks to, so that tests run without network access, `pack` or docker.
+
+use std::io;
+use std::sync::{Arc, Mutex, OnceLock};
+use rocket::figment::Figment;
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use command_runner::{CommandRunner, CommandSpec, Process};
+use crate::models::droid_state::DroidState;
+use crate::utility::docker::API_VERSION;
+use crate::utility::store::DroidStore;
+
+/// Registry-shaped responses, keyed by buildpack id
+const REGISTRY_FIXTURES: &[(&str, &str)] = &[
+    ("heroku/nodejs", r#"{
+        "latest": {"namespace": "heroku", "name": "nodejs", "version": "0.5.0", "stacks": ["heroku-18", "heroku-20", "heroku-22"]},
+        "versions": [{"version": "0.5.0"}, {"version": "0.4.0"}]
+    }"#),
+    ("heroku/ruby", r#"{
+        "latest": {"namespace": "heroku", "name": "ruby", "version": "0.1.3", "stacks": ["heroku-18", "heroku-20"]},
+        "versions": [{"version": "0.1.3"}, {"version": "0.1.2"}]
+    }"#),
+    ("heroku/go", r#"{
+        "latest": {"namespace": "heroku", "name": "go", "version": "0.3.1", "stacks": ["heroku-20", "heroku-22"]},
+        "versions": [{"version": "0.3.1"}]
+    }"#),
+    ("paketo-buildpacks/java", r#"{
+        "latest": {"namespace": "paketo-buildpacks", "name": "java", "version": "9.1.0", "stacks": ["*"]},
+        "versions": [{"version": "9.1.0"}]
+    }"#),
+    ("paketo-buildpacks/go", r#"{
+        "latest": {"namespace": "paketo-buildpacks", "name": "go", "version": "4.0.0", "stacks": ["io.buildpacks.stacks.bionic"]},
+        "versions": [{"version": "4.0.0"}]
+    }"#),
+    ("paketo-buildpacks/nodejs", r#"{
+        "latest": {"namespace": "paketo-buildpacks", "name": "nodejs", "version": "1.2.0", "stacks": ["io.buildpacks.stacks.bionic", "io.buildpacks.stacks.jammy"]},
+        "versions": [{"version": "1.2.0"}]
+    }"#),
+    ("paketo-buildpacks/web-servers", r#"{
+        "latest": {"namespace": "paketo-buildpacks", "name": "web-servers", "version": "0.10.0", "stacks": ["io.buildpacks.stacks.bionic", "io.buildpacks.stacks.jammy"]},
+        "versions": [{"version": "0.10.0"}]
+    }"#),
+    ("heroku/empty", r#"{
+        "latest": {"namespace": "heroku", "name": "empty", "stacks": []},
+        "versions": []
+    }"#),
+];
+
+/// Answers one HTTP request on `stream` with the fixture of the requested buildpack, or a 404
+async fn serve_registry(mut stream: tokio::net::TcpStream) -> io::Result<()> {
+    let mut buf = vec![0; 8192];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let id = path.trim_start_matches("/api/v1/buildpacks/");
+
+    let (status, body) = match REGISTRY_FIXTURES.iter().find(|(fixture, _)| *fixture == id) {
+        Some((_, body)) => ("200 OK", *body),
+        None => ("404 Not Found", r#"{"error": "buildpack not found"}"#),
+    };
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status, body.len(), body);
+    stream.write_all(response.as_bytes()).await
+}
+
+/// Base url of an in-process fake buildpack registry, started on first use and shared by all the tests.
+/// It runs on its own thread so that it outlives the runtime of any single test.
+pub fn registry_url() -> &'static str {
+    static URL: OnceLock<String> = OnceLock::new();
+    URL.get_or_init(|| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("fake registry can bind");
+        let url = format!("http://{}/api/v1/buildpacks", listener.local_addr().unwrap());
+        listener.set_nonblocking(true).unwrap();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                loop {
+                    if let Ok((stream, _)) = listener.accept().await {
+                        tokio::spawn(serve_registry(stream));
+                    }
+                }
+            });
+        });
+        url
+    })
+}
+
+/// Serves a fake Docker Engine API on `socket`. Each request is answered by the first route whose
+/// "<METHOD> <path>" (without the API version) starts with the route prefix, or a 404 if none matches.
+/// Returns the request lines received so far.
+pub fn fake_engine(socket: &str, routes: Vec<(&'static str, u16, &'static str)>) -> Arc<Mutex<Vec<String>>> {
+    let _ = std::fs::remove_file(socket);
+    let listener = tokio::net::UnixListener::bind(socket).unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 64 * 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            let line = String::from_utf8_lossy(&buf[..n]).lines().next().unwrap_or_default()
+                .replace(&format!("/{}", API_VERSION), "");
+            let (status, body) = routes.iter()
+                .find(|(prefix, _, _)| line.starts_with(prefix))
+                .map(|(_, status, body)| (*status, *body))
+                .unwrap_or((404, r#"{"message":"not found"}"#));
+            received.lock().unwrap().push(line);
+            let response = format!("HTTP/1.1 {} OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                                   status, body.len(), body);
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    requests
+}
+
+/// Canned result of a scripted command
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    /// Command the script answers, see `runs`
+    pub command: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+    /// Files created when the command is spawned, relative to its working directory if it has one, else to its last
+    /// absolute path argument (the target directory of a clone or checkout). Spawning a command with neither fails,
+    /// rather than leaving the files in the working directory of the tests.
+    pub files: Vec<(String, String)>,
+}
+
+impl Script {
+    pub fn ok(command: &str, stdout: &str) -> Script {
+        Script { command: command.to_string(), stdout: stdout.to_string(), ..Default::default() }
+    }
+
+    pub fn fail(command: &str, code: i32, stderr: &str) -> Script {
+        Script { command: command.to_string(), stderr: stderr.to_string(), code, ..Default::default() }
+    }
+
+    pub fn with_file(mut self, path: &str, contents: &str) -> Script {
+        self.files.push((path.to_string(), contents.to_string()));
+        self
+    }
+}
+
+/// Whether `command` runs `pattern`: its first word is the program, and its other words are arguments of the command,
+/// in the same order. "git worktree" matches `git -C <mirror> worktree add ...`, "pack build" does not match
+/// `pack builder create ...`.
+pub fn runs(command: &CommandSpec, pattern: &str) -> bool {
+    let mut words = pattern.split_whitespace();
+    if words.next() != Some(command.program.as_str()) {
+        return false;
+    }
+    let mut args = command.args.iter();
+    words.all(|word| args.any(|arg| arg == word))
+}
+
+/// Commit SHA the scripted repos are checked out at
+pub const COMMIT: &str = "3f2a9c1e5b7d4a6f8e0c2b4d6f8a0c2e4b6d8f0a";
+
+/// Scripts of the checkout of a repo: its branch resolved to `COMMIT` and the worktree created with `files`
+pub fn checkout(files: &[(&str, &str)]) -> Vec<Script> {
+    let worktree = files.iter().fold(Script::ok("git worktree", ""), |script, (path, contents)| script.with_file(path, contents));
+    vec![Script::ok("git rev-parse", &format!("{}\n", COMMIT)), worktree]
+}
+
+/// Records the commands it is asked to run and answers each with the first script left for it, which is used up.
+/// Commands without a script left succeed with no output.
+#[derive(Default)]
+pub struct ScriptedRunner {
+    scripts: Mutex<Vec<Script>>,
+    calls: Mutex<Vec<CommandSpec>>,
+}
+
+impl ScriptedRunner {
+    pub fn new(scripts: Vec<Script>) -> ScriptedRunner {
+        ScriptedRunner { scripts: Mutex::new(scripts), ..Default::default() }
+    }
+
+    pub fn calls(&self) -> Vec<CommandSpec> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// The commands run so far matching `pattern`, see `runs`
+    pub fn calls_to(&self, pattern: &str) -> Vec<CommandSpec> {
+        self.calls().into_iter().filter(|c| runs(c, pattern)).collect()
+    }
+
+    /// The command line of the last command run matching `pattern`
+    pub fn last(&self, pattern: &str) -> Option<String> {
+        self.calls_to(pattern).last().map(|c| c.line())
+    }
+}
+
+impl CommandRunner for ScriptedRunner {
+    fn spawn(&self, command: &CommandSpec) -> io::Result<Process> {
+        self.calls.lock().unwrap().push(command.clone());
+        let script = {
+            let mut scripts = self.scripts.lock().unwrap();
+            match scripts.iter().position(|script| runs(command, &script.command)) {
+                Some(index) => scripts.remove(index),
+                None => Script::default(),
+            }
+        };
+        for (path, contents) in &script.files {
+            let base = command.dir.as_deref()
+                .or(command.args.iter().rev().map(String::as_str).find(|arg| arg.starts_with('/')))
+                .ok_or_else(|| io::Error::other(format!("`{}` has no directory to create {} in", command.line(), path)))?;
+            let path = std::path::Path::new(base).join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, contents)?;
+        }
+        Ok(Process {
+            pid: None,
+            stdout: Box::new(std::io::Cursor::new(script.stdout.into_bytes())),
+            stderr: Box::new(std::io::Cursor::new(script.stderr.into_bytes())),
+            exit: Box::pin(async move { Ok(script.code) }),
+        })
+    }
+}
+
+/// Parses a server-sent events body into (id, event, data) tuples
+pub fn sse_events(body: &str) -> Vec<(u64, String, rocket::serde::json::Value)> {
+    body.split("\n\n").filter_map(|block| {
+        let field = |name: &str| block.lines().find_map(|l| l.strip_prefix(name)).map(|v| v.trim().to_string());
+        Some((field("id:")?.parse().ok()?, field("event:")?, rocket::serde::json::serde_json::from_str(&field("data:")?).ok()?))
+    }).collect()
+}
+
+/// A route of the fake docker engine: the "<METHOD> <path>" prefix it answers, its status code and body
+pub type Route = (&'static str, u16, &'static str);
+
+/// Routes of the fake docker engine for the deploys: containers are removed, created and started, and inspected as
+/// running
+pub const DEPLOY_ROUTES: &[Route] = &[
+    ("POST /containers/create", 201, r#"{"Id":"abc"}"#),
+    ("POST /containers/", 204, ""),
+    ("DELETE /containers/", 204, ""),
+    ("GET /containers/", 200, r#"{"Id":"abc","State":{"Status":"running","Running":true}}"#),
+    ("GET /networks", 200, "[]"),
+    ("POST /networks/create", 201, r#"{"Id":"net"}"#),
+];
+
+/// Files and directories removed when dropped, so that a failing test does not leave them behind
+pub struct TempPaths(pub Vec<String>);
+
+impl Drop for TempPaths {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = std::fs::remove_dir_all(path).or_else(|_| std::fs::remove_file(path));
+        }
+    }
+}
+
+/// A temporary path for the tests, unique to `name` and the test process
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("dsi-{}-{}", name, std::process::id())).to_str().unwrap().to_string()
+}
+
+/// The setup of a `TestDsi`, see `TestDsi::setup`
+pub struct TestSetup {
+    name: String,
+    scripts: Vec<Script>,
+    routes: Vec<Route>,
+    states: Vec<DroidState>,
+    config: Vec<(&'static str, Value)>,
+}
+
+impl TestSetup {
+    /// Scripts of the commands the DSI runs
+    pub fn scripts(mut self, scripts: Vec<Script>) -> TestSetup {
+        self.scripts.extend(scripts);
+        self
+    }
+
+    /// Routes the fake docker engine answers before `DEPLOY_ROUTES`
+    pub fn engine(mut self, routes: Vec<Route>) -> TestSetup {
+        self.routes.extend(routes);
+        self
+    }
+
+    /// A droid state recorded before the DSI starts
+    pub fn state(mut self, state: DroidState) -> TestSetup {
+        self.states.push(state);
+        self
+    }
+
+    pub fn config(mut self, key: &'static str, value: Value) -> TestSetup {
+        self.config.push((key, value));
+        self
+    }
+
+    pub async fn start(self) -> TestDsi {
+        let dumps_dir = temp_path(&self.name);
+        let socket = format!("{}.sock", dumps_dir);
+        let temp = TempPaths(vec![dumps_dir.clone(), socket.clone()]);
+        let _ = std::fs::remove_dir_all(&dumps_dir);
+        for state in &self.states {
+            state.save(&dumps_dir).unwrap();
+        }
+        let requests = fake_engine(&socket, self.routes.into_iter().chain(DEPLOY_ROUTES.iter().copied()).collect());
+        let runner = Arc::new(ScriptedRunner::new(self.scripts));
+        let figment = self.config.into_iter().fold(test_config(&dumps_dir, &socket), |figment, (key, value)| figment.merge((key, value)));
+        let client = Client::tracked(crate::dsi(rocket::custom(figment), runner.clone())).await.expect("valid rocket instance");
+        TestDsi { client, runner, requests, dumps_dir, _temp: temp }
+    }
+}
+
+/// The config of a DSI with its dumps in `dumps_dir`, docker reachable at `docker_socket` and the fake registry.
+/// The nginx config is rendered to `<dumps_dir>/nginx.conf`.
+fn test_config(dumps_dir: &str, docker_socket: &str) -> Figment {
+    rocket::Config::figment()
+        .merge(("dumps_dir", dumps_dir))
+        .merge(("docker_socket", docker_socket))
+        .merge(("registry_url", registry_url()))
+        .merge(("reconcile_interval", 0))
+        .merge(("nginx_config", format!("{}/nginx.conf", dumps_dir)))
+        .merge(("secret_key_file", format!("{}/secret.key", dumps_dir)))
+}
+
+/// A DSI running its commands through a `ScriptedRunner`, with its dumps in a fresh temporary directory and docker
+/// faked by `fake_engine`. Both are removed when it is dropped.
+pub struct TestDsi {
+    pub client: Client,
+    pub runner: Arc<ScriptedRunner>,
+    /// Request lines received by the fake docker engine
+    pub requests: Arc<Mutex<Vec<String>>>,
+    pub dumps_dir: String,
+    _temp: TempPaths,
+}
+
+impl TestDsi {
+    /// Sets up a DSI whose temporary paths are named after `name`, which must be unique among the tests
+    pub fn setup(name: &str) -> TestSetup {
+        TestSetup { name: name.to_string(), scripts: Vec::new(), routes: Vec::new(), states: Vec::new(), config: Vec::new() }
+    }
+
+    pub fn store(&self) -> &DroidStore {
+        self.client.rocket().state::<DroidStore>().unwrap()
+    }
+
+    /// The request lines received by the fake docker engine starting with `prefix`
+    pub fn requests_to(&self, prefix: &str) -> Vec<String> {
+        self.requests.lock().unwrap().iter().filter(|r| r.starts_with(prefix)).cloned().collect()
+    }
+
+    /// The rendered nginx config
+    pub fn nginx(&self) -> String {
+        std::fs::read_to_string(format!("{}/nginx.conf", self.dumps_dir)).unwrap()
+    }
+}
+
+#[test]
+fn test_scripted_runner() {
+    println!("The scripted runner should record the commands and answer each with the first script left for it");
+    let runner = ScriptedRunner::new(vec![Script::fail("false", 2, "boom\n"), Script::ok("echo hello", "hello\n")]);
+    let run = |command: CommandSpec| tokio_test::block_on(command_runner::output(&runner, &command)).unwrap();
+    let first = run(CommandSpec::new("echo").arg("hello"));
+    let second = run(CommandSpec::new("false"));
+    let third = run(CommandSpec::new("echo").arg("hello"));
+
+    assert_eq!(first.code, 0);
+    assert_eq!(first.stdout, "hello\n");
+    assert_eq!(second.code, 2);
+    assert_eq!(second.stderr, "boom\n");
+    assert_eq!(third.stdout, "");
+    assert_eq!(runner.calls().iter().map(|c| c.line()).collect::<Vec<String>>(), vec!["echo hello", "false", "echo hello"]);
+    assert!(runs(&CommandSpec::new("git").arg("-C").arg("/mirror").arg("worktree").arg("add"), "git worktree add"));
+    assert!(!runs(&CommandSpec::new("pack").arg("builder").arg("create"), "pack build"));
+}
@@ -0,0 +1,114 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use sha2::{Digest, Sha256};
+use command_runner::CommandSpec;
+
+/// Formats of the source archives droids can be built from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Tells the format from the first bytes of the archive, None if it is neither a gzip nor a zip file
+    pub fn detect(head: &[u8]) -> Option<ArchiveFormat> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if head.starts_with(b"PK\x03\x04") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// Extracts `archive` into `dir`. `tar` strips leading `/` and skips members with `..`, and only creates the
+    /// symlinks leaving the archive once everything else is extracted, so that no member is written through them.
+    pub fn extract_command(&self, archive: &str, dir: &str) -> CommandSpec {
+        match self {
+            ArchiveFormat::TarGz => CommandSpec::new("tar")
+                .arg("-xzf").arg(archive)
+                .arg("--no-same-owner")
+                .arg("--no-same-permissions")
+                .arg("-C").arg(dir),
+            ArchiveFormat::Zip => CommandSpec::new("unzip")
+                .arg("-q")
+                .arg("-o").arg(archive)
+                .arg("-d").arg(dir),
+        }
+    }
+}
+
+/// `sha256:<hex>` digest of the file at `path`
+pub fn digest(path: &str) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Fails if a symlink extracted to `dir` leads out of it, following the symlinks it goes through
+pub fn check_symlinks(dir: &str) -> Result<(), String> {
+    let root = Path::new(dir).canonicalize().map_err(|e| e.to_string())?;
+    let mut pending = vec![root.clone()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            let metadata = std::fs::symlink_metadata(&path).map_err(|e| e.to_string())?;
+            if metadata.file_type().is_symlink() {
+                let target = std::fs::read_link(&path).map_err(|e| e.to_string())?;
+                let inside = match path.canonicalize() {
+                    Ok(resolved) => resolved.starts_with(&root),
+                    // dangling, its target may still be created later
+                    Err(_) => lexically_inside(&root, &path.parent().unwrap_or(&root).join(&target)),
+                };
+                if !inside {
+                    let name = path.strip_prefix(&root).unwrap_or(&path).display().to_string();
+                    return Err(format!("{} links to {}, outside of the archive", name, target.display()));
+                }
+            } else if metadata.is_dir() {
+                pending.push(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` stays inside `root` once its `.` and `..` are resolved, without following symlinks
+fn lexically_inside(root: &Path, path: &Path) -> bool {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => if !resolved.pop() { return false; },
+            Component::CurDir => {}
+            component => resolved.push(component),
+        }
+    }
+    resolved.starts_with(root)
+}
+
+#[test]
+fn test_archive() {
+    println!("Archives should be told apart by their first bytes");
+    assert_eq!(ArchiveFormat::detect(&[0x1f, 0x8b, 0x08]), Some(ArchiveFormat::TarGz));
+    assert_eq!(ArchiveFormat::detect(b"PK\x03\x04\x14"), Some(ArchiveFormat::Zip));
+    assert_eq!(ArchiveFormat::detect(b"<html>"), None);
+    assert_eq!(ArchiveFormat::Zip.extract_command("/a.zip", "/src").line(), "unzip -q -o /a.zip -d /src");
+
+    println!("Symlinks leading out of the extracted archive should be rejected, dangling or not");
+    let dir = std::env::temp_dir().join(format!("dsi-archive-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("src/lib")).unwrap();
+    std::fs::write(dir.join("file"), "contents").unwrap();
+    assert_eq!(digest(dir.join("file").to_str().unwrap()).unwrap(),
+               "sha256:d1b2a59fbea7e20077af9f91b27e95e865061b270be03ff539ab3b73587882e8");
+    std::os::unix::fs::symlink("../file", dir.join("src/link")).unwrap();
+    std::os::unix::fs::symlink("lib/missing", dir.join("src/dangling")).unwrap();
+    let dir_str = dir.to_str().unwrap();
+    assert_eq!(check_symlinks(dir_str), Ok(()));
+    std::os::unix::fs::symlink("../../../etc/passwd", dir.join("src/lib/escape")).unwrap();
+    assert_eq!(check_symlinks(dir_str), Err("src/lib/escape links to ../../../etc/passwd, outside of the archive".to_string()));
+    std::fs::remove_file(dir.join("src/lib/escape")).unwrap();
+    std::os::unix::fs::symlink("/nonexistent", dir.join("absolute")).unwrap();
+    assert!(check_symlinks(dir_str).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::Serialize;
+use tokio::sync::watch;
+use crate::models::detection::BuildPlan;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BuildEventKind {
+    /// A build stage started: clone, builder, build or deploy
+    Stage { stage: String },
+    /// The buildpacks and stack detected for a droid that declares no buildpacks, sent before it is built
+    Plan { plan: BuildPlan },
+    /// A lifecycle phase of `pack build` started, or ended after `duration_ms`
+    Phase { phase: String, duration_ms: Option<u64> },
+    Stdout { line: String },
+    Stderr { line: String },
+    /// The command of the current stage exited
+    Exit { code: i32 },
+    /// The build failed, `code` is machine-readable (see `Lifecycle::failure`)
+    Error { code: String, message: String },
+}
+
+/// One event of a build, `id` is its sequence number in the build log (starting at 1)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BuildEvent {
+    pub id: u64,
+    /// Milliseconds since the unix epoch
+    pub timestamp: u128,
+    #[serde(flatten)]
+    pub kind: BuildEventKind,
+}
+
+impl BuildEvent {
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            BuildEventKind::Stage { .. } => "stage",
+            BuildEventKind::Plan { .. } => "plan",
+            BuildEventKind::Phase { .. } => "phase",
+            BuildEventKind::Stdout { .. } => "stdout",
+            BuildEventKind::Stderr { .. } => "stderr",
+            BuildEventKind::Exit { .. } => "exit",
+            BuildEventKind::Error { .. } => "error",
+        }
+    }
+
+    pub fn to_sse(&self) -> Event {
+        Event::json(self).event(self.name()).id(self.id.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Events {
+    events: Vec<BuildEvent>,
+    finished: bool,
+}
+
+/// The events of one build, kept in memory so that clients can (re)connect at any time.
+#[derive(Debug)]
+pub struct BuildLog {
+    events: Mutex<Events>,
+    /// Number of events pushed so far, subscribers wait for it to change
+    updates: watch::Sender<usize>,
+}
+
+impl Default for BuildLog {
+    fn default() -> Self {
+        BuildLog { events: Mutex::new(Events::default()), updates: watch::channel(0).0 }
+    }
+}
+
+impl BuildLog {
+    pub fn push(&self, kind: BuildEventKind) {
+        let mut events = self.events.lock().unwrap();
+        let event = BuildEvent {
+            id: events.events.len() as u64 + 1,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default(),
+            kind,
+        };
+        events.events.push(event);
+        self.updates.send_replace(events.events.len());
+    }
+
+    /// Marks the build as done, subscribers end their stream once they have seen every event.
+    pub fn finish(&self) {
+        self.events.lock().unwrap().finished = true;
+        self.updates.send_modify(|_| {});
+    }
+
+    /// Returns the events after `last_id`, and whether the build is done
+    pub fn since(&self, last_id: u64) -> (Vec<BuildEvent>, bool) {
+        let events = self.events.lock().unwrap();
+        (events.events.iter().skip(last_id as usize).cloned().collect(), events.finished)
+    }
+
+    /// Streams the events after `last_id` as server-sent events, until the build is done.
+    pub fn subscribe(self: Arc<Self>, last_id: u64) -> EventStream![] {
+        let mut updates = self.updates.subscribe();
+        EventStream! {
+            let mut last_id = last_id;
+            loop {
+                let (events, finished) = self.since(last_id);
+                for event in events {
+                    last_id = event.id;
+                    yield event.to_sse();
+                }
+                if finished || updates.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The `Last-Event-ID` header sent by a reconnecting event stream client, 0 if absent or invalid.
+pub struct LastEventId(pub u64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let id = request.headers().get_one("Last-Event-ID").and_then(|id| id.trim().parse().ok());
+        Outcome::Success(LastEventId(id.unwrap_or(0)))
+    }
+}
+
+/// In-memory map of the build logs, the last build of each droid is kept.
+#[derive(Debug, Clone, Default)]
+pub struct BuildLogs {
+    logs: Arc<Mutex<HashMap<i64, Arc<BuildLog>>>>,
+}
+
+impl BuildLogs {
+    /// Starts a new build log for the droid, replacing the previous one
+    pub fn start(&self, app_id: i64) -> Arc<BuildLog> {
+        let log = Arc::new(BuildLog::default());
+        self.logs.lock().unwrap().insert(app_id, log.clone());
+        log
+    }
+
+    pub fn get(&self, app_id: i64) -> Option<Arc<BuildLog>> {
+        self.logs.lock().unwrap().get(&app_id).cloned()
+    }
+}
+
+#[test]
+fn test_build_log() {
+    println!("Events should be numbered from 1 and be replayable after any event id");
+    let log = BuildLog::default();
+    log.push(BuildEventKind::Stage { stage: "clone".to_string() });
+    log.push(BuildEventKind::Stdout { line: "Cloning...".to_string() });
+    log.push(BuildEventKind::Exit { code: 0 });
+
+    let (events, finished) = log.since(1);
+    assert_eq!(events.iter().map(|e| (e.id, e.name())).collect::<Vec<_>>(), vec![(2, "stdout"), (3, "exit")]);
+    assert!(!finished);
+
+    log.finish();
+    assert_eq!(log.since(3), (vec![], true));
+}
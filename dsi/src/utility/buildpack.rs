@@ -1,13 +1,18 @@
 use rocket::serde::json::{serde_json, Value};
 
-pub async fn fetch_buildpack_info(buildpack: &String) -> Result<Value, String> {
-    let buildpack_url = "https://cnb-registry-api-staging.herokuapp.com//api/v1/buildpacks/".to_string() + buildpack;
+/// Fetches the info of `buildpack` ("<namespace>/<name>") from the registry at `registry_url`.
+pub async fn fetch_buildpack_info(registry_url: &str, buildpack: &str) -> Result<Value, String> {
+    let buildpack_url = format!("{}/{}", registry_url, buildpack);
 
     let response = match reqwest::get(&buildpack_url).await {
         Ok(response) => response,
         Err(err) => return Err(format!("Error fetching buildpack info: {}", err))
     };
 
+    if !response.status().is_success() {
+        return Err(format!("Buildpack {} not found in registry ({})", buildpack, response.status()));
+    }
+
     let response = match response.text().await {
         Ok(response) => response,
         Err(err) => return Err(format!("Error getting buildpack info: {}", err))
@@ -24,9 +29,13 @@ pub async fn fetch_buildpack_info(buildpack: &String) -> Result<Value, String> {
 #[test]
 fn test_fetch_buildpack_info() {
     println!("Fetched info for buildpack paketo-buildpacks/java should be a json object, with 'stacks' array");
-    let buildpack = "paketo-buildpacks/java".to_string();
-    let response = tokio_test::block_on(fetch_buildpack_info(&buildpack));
+    let registry = crate::test_support::registry_url();
+    let response = tokio_test::block_on(fetch_buildpack_info(registry, "paketo-buildpacks/java"));
     assert!(response.is_ok());
     let response = response.unwrap();
     assert!(response["latest"]["stacks"].is_array());
-}
\ No newline at end of file
+
+    println!("Fetching an unknown buildpack should fail");
+    let response = tokio_test::block_on(fetch_buildpack_info(registry, "heroku/unknown"));
+    assert_eq!(response.unwrap_err(), "Buildpack heroku/unknown not found in registry (404 Not Found)");
+}
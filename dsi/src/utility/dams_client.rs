@@ -0,0 +1,198 @@
+use std::time::Duration;
+use rocket::serde::json::Value;
+use rocket::serde::json::serde_json::json;
+use crate::config::DsiConfig;
+use crate::utility::host;
+use crate::utility::store::DroidStore;
+
+/// Announces this droid-server to DAMS, so that it can place droids on it: registers on startup, sends
+/// heartbeats with the capacity and droid states, and deregisters on shutdown.
+#[derive(Debug, Clone)]
+pub struct DamsClient {
+    /// Base url of DAMS, nothing is sent if None
+    pub url: Option<String>,
+    /// Url DAMS reaches this DSI at
+    pub advertise_url: String,
+    pub server_id: String,
+    pub domains: Vec<String>,
+    pub stacks: Vec<String>,
+    pub builder_cache: bool,
+    pub heartbeat_interval: Duration,
+}
+
+impl DamsClient {
+    /// `default_url` is used if no `advertise_url` is configured
+    pub fn new(config: &DsiConfig, default_url: String) -> DamsClient {
+        DamsClient {
+            url: config.dams_url.as_ref().map(|url| url.trim_end_matches('/').to_string()),
+            advertise_url: config.advertise_url.clone().unwrap_or(default_url),
+            server_id: config.server_id.clone(),
+            domains: config.domains.clone(),
+            stacks: config.stacks.clone(),
+            builder_cache: config.builder_cache,
+            heartbeat_interval: Duration::from_secs(config.heartbeat_interval.max(1)),
+        }
+    }
+
+    pub fn registration(&self) -> Value {
+        json!({
+            "server_id": self.server_id,
+            "url": self.advertise_url,
+            "domains": self.domains,
+            "version": env!("CARGO_PKG_VERSION"),
+            "capabilities": {
+                "stacks": self.stacks,
+                "builder_cache": self.builder_cache
+            }
+        })
+    }
+
+    pub fn heartbeat(&self, store: &DroidStore) -> Value {
+        let states = store.all();
+        json!({
+            "capacity": host::capacity(states.len()),
+            "droids": states.iter().map(|state| json!({
+                "app_id": state.app_id,
+                "uid": state.uid,
+                "status": state.status
+            })).collect::<Vec<Value>>()
+        })
+    }
+
+    /// Sends a request to DAMS, returning its status code
+    async fn send(&self, method: reqwest::Method, path: &str, body: Option<Value>) -> Result<u16, String> {
+        let url = match &self.url {
+            Some(url) => format!("{}{}", url, path),
+            None => return Ok(200)
+        };
+        let mut request = reqwest::Client::new().request(method, &url);
+        if let Some(body) = body {
+            request = request.header("Content-Type", "application/json").body(body.to_string());
+        }
+        let response = request.send().await.map_err(|e| format!("Error reaching DAMS at {}: {}", url, e))?;
+        Ok(response.status().as_u16())
+    }
+
+    pub async fn register(&self) -> Result<(), String> {
+        match self.send(reqwest::Method::POST, "/servers", Some(self.registration())).await? {
+            200..=299 => Ok(()),
+            code => Err(format!("DAMS refused the registration of {} ({})", self.server_id, code)),
+        }
+    }
+
+    /// Sends a heartbeat, false if DAMS does not know this server (anymore) and it has to register again
+    pub async fn send_heartbeat(&self, store: &DroidStore) -> Result<bool, String> {
+        let path = format!("/servers/{}/heartbeat", self.server_id);
+        match self.send(reqwest::Method::PUT, &path, Some(self.heartbeat(store))).await? {
+            200..=299 => Ok(true),
+            404 => Ok(false),
+            code => Err(format!("DAMS refused the heartbeat of {} ({})", self.server_id, code)),
+        }
+    }
+
+    pub async fn deregister(&self) -> Result<(), String> {
+        let path = format!("/servers/{}", self.server_id);
+        match self.send(reqwest::Method::DELETE, &path, None).await? {
+            200..=299 | 404 => Ok(()),
+            code => Err(format!("DAMS refused the deregistration of {} ({})", self.server_id, code)),
+        }
+    }
+
+    /// Registers, then sends a heartbeat every `heartbeat_interval`. The registration is retried on the next
+    /// heartbeat if DAMS is unreachable or forgot about this server.
+    pub async fn run(self, store: DroidStore) {
+        if self.url.is_none() {
+            return;
+        }
+        let mut registered = false;
+        loop {
+            if !registered {
+                match self.register().await {
+                    Ok(()) => {
+                        println!("Registered with DAMS as {}", self.server_id);
+                        registered = true;
+                    }
+                    Err(e) => println!("{}", e)
+                }
+            }
+            if registered {
+                match self.send_heartbeat(&store).await {
+                    Ok(known) => registered = known,
+                    Err(e) => println!("{}", e)
+                }
+            }
+            tokio::time::sleep(self.heartbeat_interval).await;
+        }
+    }
+}
+
+#[test]
+fn test_payloads() {
+    println!("The registration should announce the server and the heartbeat its droids");
+    let config = DsiConfig { stacks: vec!["io.buildpacks.stacks.jammy".to_string()], ..Default::default() };
+    let dams = DamsClient::new(&config, "http://127.0.0.1:8000".to_string());
+    let registration = dams.registration();
+    assert_eq!(registration["server_id"], "ds1");
+    assert_eq!(registration["url"], "http://127.0.0.1:8000");
+    assert_eq!(registration["capabilities"]["stacks"], json!(["io.buildpacks.stacks.jammy"]));
+
+    let dir = std::env::temp_dir().join(format!("dsi-dams-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = DroidStore::load(dir.to_str().unwrap()).unwrap();
+    store.save(crate::models::droid_state::DroidState::new(1, "7d6g824")).unwrap();
+    let heartbeat = dams.heartbeat(&store);
+    assert_eq!(heartbeat["capacity"]["droids"], 1);
+    assert_eq!(heartbeat["droids"][0]["uid"], "7d6g824");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[rocket::async_test]
+async fn test_reregistration() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    println!("A heartbeat refused by DAMS should trigger a new registration");
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = DsiConfig { dams_url: Some(format!("http://{}/", listener.local_addr().unwrap())), ..Default::default() };
+    let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+    let received = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 64 * 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            let line = String::from_utf8_lossy(&buf[..n]).lines().next().unwrap_or_default().to_string();
+            let forgotten = {
+                let mut received = received.lock().unwrap();
+                // DAMS restarted after the first registration, and forgot about the server
+                let forgotten = line.starts_with("PUT") && !received.iter().any(|l| l.starts_with("PUT"));
+                received.push(line);
+                forgotten
+            };
+            let status = if forgotten { "404 Not Found" } else { "200 OK" };
+            let response = format!("HTTP/1.1 {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}", status);
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    let dir = std::env::temp_dir().join(format!("dsi-dams-run-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = DroidStore::load(dir.to_str().unwrap()).unwrap();
+    let dams = DamsClient::new(&config, "http://127.0.0.1:8000".to_string());
+    assert_eq!(dams.heartbeat_interval, Duration::from_secs(10));
+    let dams = DamsClient { heartbeat_interval: Duration::from_millis(20), ..dams };
+    let task = tokio::spawn(dams.clone().run(store));
+    for _ in 0..250 {
+        if requests.lock().unwrap().len() >= 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    task.abort();
+    dams.deregister().await.unwrap();
+
+    let lines = requests.lock().unwrap().clone();
+    let lines: Vec<&str> = lines.iter().map(|l| l.trim_end_matches(" HTTP/1.1")).collect();
+    assert_eq!(&lines[..4], &["POST /servers", "PUT /servers/ds1/heartbeat", "POST /servers", "PUT /servers/ds1/heartbeat"]);
+    assert_eq!(lines.last(), Some(&"DELETE /servers/ds1"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
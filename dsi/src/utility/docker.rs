@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::serde::de::DeserializeOwned;
+use rocket::serde::json::{serde_json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// Label set on every droid container, its value is the app id of the droid.
+pub const DROID_LABEL: &str = "appoxy.droid";
+
+/// Docker Engine API version used for every call
+pub const API_VERSION: &str = "v1.41";
+
+/// Error of a Docker Engine API call, with the status code docker answered with, if it answered
+#[derive(Debug, Clone, PartialEq)]
+pub struct DockerError {
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl DockerError {
+    fn new(message: String) -> DockerError {
+        DockerError { status: None, message }
+    }
+
+    /// Whether docker answered that the container or image does not exist
+    pub fn is_not_found(&self) -> bool {
+        self.status == Some(404)
+    }
+}
+
+impl std::fmt::Display for DockerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<DockerError> for String {
+    fn from(err: DockerError) -> String {
+        err.message
+    }
+}
+
+/// Client for the Docker Engine API, talking HTTP/1.1 over the docker unix socket.
+/// https://docs.docker.com/engine/api/v1.41/
+#[derive(Debug, Clone)]
+pub struct DockerClient {
+    socket: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerSummary {
+    pub id: String,
+    /// Container names, prefixed with a "/"
+    pub names: Vec<String>,
+    pub image: String,
+    /// created, running, paused, restarting, removing, exited or dead
+    pub state: String,
+    pub labels: HashMap<String, String>,
+}
+
+impl ContainerSummary {
+    pub fn name(&self) -> String {
+        self.names.first().map(|n| n.trim_start_matches('/').to_string()).unwrap_or_default()
+    }
+
+    /// App id of the droid running in this container, read from the droid label
+    pub fn app_id(&self) -> Option<i64> {
+        self.labels.get(DROID_LABEL).and_then(|id| id.parse().ok())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerInspect {
+    pub id: String,
+    pub name: String,
+    pub state: ContainerState,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ContainerState {
+    pub status: String,
+    pub running: bool,
+    #[serde(rename = "OOMKilled")]
+    pub oom_killed: bool,
+    pub exit_code: i64,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// Body of a container creation request
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerSpec {
+    pub image: String,
+    pub env: Vec<String>,
+    pub labels: HashMap<String, String>,
+    pub host_config: HostConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub networking_config: Option<NetworkingConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase")]
+pub struct HostConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_mode: Option<String>,
+    /// Cpu quota in units of 1e-9 cpus
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nano_cpus: Option<i64>,
+    /// Memory limit in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<i64>,
+    /// Memory plus swap limit in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_swap: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<i64>,
+}
+
+/// Networks a container is attached to on creation, keyed by network name
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase")]
+pub struct NetworkingConfig {
+    pub endpoints_config: HashMap<String, EndpointConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase")]
+pub struct EndpointConfig {
+    /// Other names the container is reachable at on the network
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ImageSummary {
+    pub id: String,
+    pub repo_tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "PascalCase", default)]
+pub struct NetworkSummary {
+    pub id: String,
+    pub name: String,
+    pub driver: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LogLine {
+    /// "stdout" or "stderr"
+    pub stream: String,
+    pub line: String,
+}
+
+/// One-shot container stats, only the fields the DSI reports are kept
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct ContainerStats {
+    pub read: String,
+    pub cpu_stats: CpuStats,
+    pub precpu_stats: CpuStats,
+    pub memory_stats: MemoryStats,
+    pub networks: HashMap<String, NetworkStats>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct CpuStats {
+    pub cpu_usage: CpuUsage,
+    pub system_cpu_usage: u64,
+    pub online_cpus: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct CpuUsage {
+    pub total_usage: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct MemoryStats {
+    pub usage: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct NetworkStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+impl ContainerStats {
+    /// CPU usage since the previous sample, in percent of one cpu (up to 100 times the number of cpus)
+    pub fn cpu_percent(&self) -> f64 {
+        let cpu_delta = self.cpu_stats.cpu_usage.total_usage.saturating_sub(self.precpu_stats.cpu_usage.total_usage);
+        let system_delta = self.cpu_stats.system_cpu_usage.saturating_sub(self.precpu_stats.system_cpu_usage);
+        if cpu_delta == 0 || system_delta == 0 {
+            return 0.0;
+        }
+        cpu_delta as f64 / system_delta as f64 * self.cpu_stats.online_cpus.max(1) as f64 * 100.0
+    }
+
+    /// Bytes received and sent, summed over the networks of the container
+    pub fn network_totals(&self) -> NetworkStats {
+        self.networks.values().fold(NetworkStats::default(), |total, network| NetworkStats {
+            rx_bytes: total.rx_bytes + network.rx_bytes,
+            tx_bytes: total.tx_bytes + network.tx_bytes,
+        })
+    }
+}
+
+/// Seconds since the epoch of a docker RFC 3339 timestamp, i.e. "2022-11-20T14:03:51.104216387Z".
+/// Docker always reports UTC, and "0001-01-01T00:00:00Z" for containers that never started.
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.trim_end_matches('Z').split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.split('.').next()?.splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    // days from the civil date, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// Percent-encodes a query string value
+fn encode(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// Decodes a chunked transfer-encoded body
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n").ok_or("Malformed chunked body")?;
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .map_err(|e| format!("Malformed chunk size: {}", e))?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let start = line_end + 2;
+        if body.len() < start + size {
+            return Err("Truncated chunked body".to_string());
+        }
+        decoded.extend_from_slice(&body[start..start + size]);
+        body = &body[(start + size + 2).min(body.len())..];
+    }
+}
+
+/// Splits the multiplexed stdout/stderr stream of a container without TTY into lines.
+/// Each frame starts with an 8 bytes header: [stream type, 0, 0, 0, size (u32 big endian)].
+fn demux(mut body: &[u8]) -> Vec<LogLine> {
+    let mut lines = Vec::new();
+    while body.len() >= 8 && body[0] <= 2 && body[1..4] == [0, 0, 0] {
+        let size = u32::from_be_bytes([body[4], body[5], body[6], body[7]]) as usize;
+        let end = (8 + size).min(body.len());
+        let stream = if body[0] == 2 { "stderr" } else { "stdout" };
+        for line in String::from_utf8_lossy(&body[8..end]).lines() {
+            lines.push(LogLine { stream: stream.to_string(), line: line.to_string() });
+        }
+        body = &body[end..];
+    }
+    // containers with a TTY are not multiplexed
+    for line in String::from_utf8_lossy(body).lines() {
+        lines.push(LogLine { stream: "stdout".to_string(), line: line.to_string() });
+    }
+    lines
+}
+
+impl DockerClient {
+    pub fn new(socket: &str) -> DockerClient {
+        DockerClient { socket: socket.to_string() }
+    }
+
+    /// Sends a request and returns the status code and the (dechunked) body
+    async fn request(&self, method: &str, path: &str, body: Option<Value>) -> Result<(u16, Vec<u8>), DockerError> {
+        let mut stream = match UnixStream::connect(&self.socket).await {
+            Ok(stream) => stream,
+            Err(err) => return Err(DockerError::new(format!("Error connecting to docker at {}: {}", self.socket, err)))
+        };
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        let request = format!(
+            "{} /{}{} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method, API_VERSION, path, body.len(), body
+        );
+        let mut raw = Vec::new();
+        if let Err(err) = async {
+            stream.write_all(request.as_bytes()).await?;
+            stream.read_to_end(&mut raw).await
+        }.await {
+            return Err(DockerError::new(format!("Error talking to docker: {}", err)));
+        }
+
+        let malformed = |message: &str| DockerError::new(message.to_string());
+        let header_end = raw.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(|| malformed("Malformed docker response"))?;
+        let head = String::from_utf8_lossy(&raw[..header_end]).to_lowercase();
+        let status = head.split_whitespace().nth(1).and_then(|s| s.parse().ok()).ok_or_else(|| malformed("Malformed docker status line"))?;
+        let body = &raw[header_end + 4..];
+        let body = if head.contains("transfer-encoding: chunked") { dechunk(body).map_err(DockerError::new)? } else { body.to_vec() };
+
+        Ok((status, body))
+    }
+
+    /// Sends a request and fails with docker's error message on a 4xx/5xx status
+    async fn call(&self, method: &str, path: &str, body: Option<Value>) -> Result<Vec<u8>, DockerError> {
+        let (status, body) = self.request(method, path, body).await?;
+        if status >= 400 {
+            let message = serde_json::from_slice::<Value>(&body).ok()
+                .and_then(|v| v["message"].as_str().map(|m| m.to_string()))
+                .unwrap_or_else(|| String::from_utf8_lossy(&body).to_string());
+            return Err(DockerError {
+                status: Some(status),
+                message: format!("Docker {} {} failed ({}): {}", method, path, status, message),
+            });
+        }
+        Ok(body)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, DockerError> {
+        let body = self.call("GET", path, None).await?;
+        serde_json::from_slice(&body).map_err(|e| DockerError::new(format!("Error parsing docker response for {}: {}", path, e)))
+    }
+
+    /// Lists all droid containers, including the stopped ones.
+    pub async fn list_containers(&self) -> Result<Vec<ContainerSummary>, DockerError> {
+        let filters = format!("{{\"label\":[\"{}\"]}}", DROID_LABEL);
+        self.get(&format!("/containers/json?all=true&filters={}", encode(&filters))).await
+    }
+
+    pub async fn inspect_container(&self, name: &str) -> Result<ContainerInspect, DockerError> {
+        self.get(&format!("/containers/{}/json", encode(name))).await
+    }
+
+    /// Creates a container and returns its id
+    pub async fn create_container(&self, name: &str, spec: &ContainerSpec) -> Result<String, DockerError> {
+        let body = serde_json::to_value(spec).map_err(|e| DockerError::new(e.to_string()))?;
+        let body = self.call("POST", &format!("/containers/create?name={}", encode(name)), Some(body)).await?;
+        let created: Value = serde_json::from_slice(&body).map_err(|e| DockerError::new(e.to_string()))?;
+        Ok(created["Id"].as_str().unwrap_or_default().to_string())
+    }
+
+    pub async fn start_container(&self, name: &str) -> Result<(), DockerError> {
+        self.call("POST", &format!("/containers/{}/start", encode(name)), None).await.map(|_| ())
+    }
+
+    pub async fn stop_container(&self, name: &str) -> Result<(), DockerError> {
+        self.call("POST", &format!("/containers/{}/stop", encode(name)), None).await.map(|_| ())
+    }
+
+    pub async fn restart_container(&self, name: &str) -> Result<(), DockerError> {
+        self.call("POST", &format!("/containers/{}/restart", encode(name)), None).await.map(|_| ())
+    }
+
+    pub async fn remove_container(&self, name: &str) -> Result<(), DockerError> {
+        self.call("DELETE", &format!("/containers/{}?force=true", encode(name)), None).await.map(|_| ())
+    }
+
+    /// Returns the last `tail` lines of the container logs
+    pub async fn logs(&self, name: &str, tail: usize) -> Result<Vec<LogLine>, DockerError> {
+        let body = self.call("GET", &format!("/containers/{}/logs?stdout=true&stderr=true&tail={}", encode(name), tail), None).await?;
+        Ok(demux(&body))
+    }
+
+    pub async fn stats(&self, name: &str) -> Result<ContainerStats, DockerError> {
+        self.get(&format!("/containers/{}/stats?stream=false", encode(name))).await
+    }
+
+    pub async fn list_images(&self) -> Result<Vec<ImageSummary>, DockerError> {
+        self.get("/images/json").await
+    }
+
+    pub async fn remove_image(&self, image: &str) -> Result<(), DockerError> {
+        self.call("DELETE", &format!("/images/{}", encode(image)), None).await.map(|_| ())
+    }
+
+    pub async fn list_networks(&self) -> Result<Vec<NetworkSummary>, DockerError> {
+        self.get("/networks").await
+    }
+
+    /// Creates a bridge network and returns its id
+    pub async fn create_network(&self, name: &str) -> Result<String, DockerError> {
+        let body = serde_json::json!({ "Name": name, "Driver": "bridge", "CheckDuplicate": true });
+        let body = self.call("POST", "/networks/create", Some(body)).await?;
+        let created: Value = serde_json::from_slice(&body).map_err(|e| DockerError::new(e.to_string()))?;
+        Ok(created["Id"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+#[test]
+fn test_docker_client() {
+    println!("The client should parse typed responses and docker error messages from a fake docker socket");
+    let socket = std::env::temp_dir().join(format!("fake-docker-{}.sock", std::process::id()));
+    let socket = socket.to_str().unwrap().to_string();
+    tokio_test::block_on(async {
+        let requests = crate::test_support::fake_engine(&socket, vec![
+            ("GET /containers/json", 200, r#"[{"Id":"abc","Names":["/1"],"State":"exited","Labels":{"appoxy.droid":"1"}}]"#),
+            ("POST /containers/2/start", 404, r#"{"message":"No such container: 2"}"#),
+            ("DELETE /containers/a404b", 500, r#"{"message":"removal in progress"}"#),
+            ("GET /networks", 200, r#"[{"Id":"n1","Name":"droid-net","Driver":"bridge"}]"#),
+            ("POST /networks/create", 201, r#"{"Id":"n2","Warning":""}"#),
+        ]);
+        let client = DockerClient::new(&socket);
+
+        let containers = client.list_containers().await.unwrap();
+        assert_eq!(containers[0].name(), "1");
+        assert_eq!(containers[0].app_id(), Some(1));
+        assert_eq!(containers[0].state, "exited");
+
+        let error = client.start_container("2").await.unwrap_err();
+        assert!(error.message.contains("No such container: 2"));
+        assert!(error.is_not_found());
+        let error = client.remove_container("a404b").await.unwrap_err();
+        assert_eq!(error.status, Some(500));
+        assert!(!error.is_not_found());
+
+        let networks = client.list_networks().await.unwrap();
+        assert_eq!(networks, vec![NetworkSummary { id: "n1".to_string(), name: "droid-net".to_string(), driver: "bridge".to_string() }]);
+        assert_eq!(client.create_network("other-net").await.unwrap(), "n2");
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("GET /containers/json?all=true&filters=%7B%22label%22"));
+        assert_eq!(requests[1], "POST /containers/2/start HTTP/1.1");
+    });
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn test_demux() {
+    let mut body = vec![1, 0, 0, 0, 0, 0, 0, 6];
+    body.extend_from_slice(b"hello\n");
+    body.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 5]);
+    body.extend_from_slice(b"oops\n");
+    assert_eq!(demux(&body), vec![
+        LogLine { stream: "stdout".to_string(), line: "hello".to_string() },
+        LogLine { stream: "stderr".to_string(), line: "oops".to_string() },
+    ]);
+}
+
+#[test]
+fn test_stats() {
+    println!("CPU usage should be computed from the delta with the previous sample, and network usage summed");
+    let sample = |total_usage, system_cpu_usage| CpuStats { cpu_usage: CpuUsage { total_usage }, system_cpu_usage, online_cpus: 4 };
+    let stats = ContainerStats {
+        cpu_stats: sample(3_000, 20_000),
+        precpu_stats: sample(1_000, 10_000),
+        networks: [
+            ("eth0".to_string(), NetworkStats { rx_bytes: 100, tx_bytes: 10 }),
+            ("eth1".to_string(), NetworkStats { rx_bytes: 50, tx_bytes: 5 }),
+        ].into(),
+        ..Default::default()
+    };
+    assert_eq!(stats.cpu_percent(), 80.0);
+    assert_eq!(stats.network_totals(), NetworkStats { rx_bytes: 150, tx_bytes: 15 });
+    assert_eq!(ContainerStats::default().cpu_percent(), 0.0);
+
+    assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(parse_timestamp("2022-11-20T14:03:51.104216387Z"), Some(1668953031));
+    assert_eq!(parse_timestamp("0001-01-01T00:00:00Z"), None);
+    assert_eq!(parse_timestamp("yesterday"), None);
+}
@@ -0,0 +1,77 @@
+use rocket::serde::Serialize;
+use command_runner::{CommandRunner, CommandSpec};
+
+/// Resources of the droid-server, as reported to DAMS
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Capacity {
+    /// Bytes
+    pub memory_total: u64,
+    /// Bytes available to new processes, without swapping
+    pub memory_free: u64,
+    pub cpus: u32,
+    /// Fraction of the cpus in use, from 0.0 to 1.0, estimated from the 1 minute load average
+    pub cpu_usage: f64,
+    /// Droids recorded on the server
+    pub droids: usize,
+}
+
+/// (total, available) memory in bytes, parsed from the contents of /proc/meminfo
+pub fn parse_meminfo(meminfo: &str) -> (u64, u64) {
+    let field = |name: &str| meminfo.lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|value| value.trim_start_matches(':').trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .unwrap_or(0);
+    (field("MemTotal"), field("MemAvailable"))
+}
+
+/// The 1 minute load average, parsed from the contents of /proc/loadavg
+pub fn parse_loadavg(loadavg: &str) -> f64 {
+    loadavg.split_whitespace().next().and_then(|load| load.parse().ok()).unwrap_or(0.0)
+}
+
+pub fn cpus() -> u32 {
+    std::thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1)
+}
+
+/// The current capacity of the host. Memory and load are 0 where /proc is not available.
+pub fn capacity(droids: usize) -> Capacity {
+    let (memory_total, memory_free) = parse_meminfo(&std::fs::read_to_string("/proc/meminfo").unwrap_or_default());
+    let cpus = cpus();
+    let load = parse_loadavg(&std::fs::read_to_string("/proc/loadavg").unwrap_or_default());
+    Capacity {
+        memory_total,
+        memory_free,
+        cpus,
+        cpu_usage: (load / cpus as f64).clamp(0.0, 1.0),
+        droids,
+    }
+}
+
+/// (total, available) bytes of the filesystem of `path`, from `df`
+pub async fn disk(runner: &dyn CommandRunner, path: &str) -> Result<(u64, u64), String> {
+    let command = CommandSpec::new("df").arg("-B1").arg("--output=size,avail").arg(path);
+    let output = command_runner::output(runner, &command).await.map_err(|e| format!("Error running df: {}", e))?;
+    if output.code != 0 {
+        return Err(format!("df {} failed: {}", path, output.stderr.trim()));
+    }
+    parse_df(&output.stdout).ok_or_else(|| format!("Unexpected df output: {}", output.stdout.trim()))
+}
+
+/// Parses the second line of `df -B1 --output=size,avail`
+pub fn parse_df(stdout: &str) -> Option<(u64, u64)> {
+    let mut fields = stdout.lines().nth(1)?.split_whitespace().map(|field| field.parse::<u64>().ok());
+    Some((fields.next()??, fields.next()??))
+}
+
+#[test]
+fn test_parse() {
+    println!("Memory should be read in bytes and the load from the first loadavg field");
+    let meminfo = "MemTotal:        8053484 kB\nMemFree:          511200 kB\nMemAvailable:    4026744 kB\n";
+    assert_eq!(parse_meminfo(meminfo), (8053484 * 1024, 4026744 * 1024));
+    assert_eq!(parse_meminfo(""), (0, 0));
+    assert_eq!(parse_loadavg("0.52 0.58 0.59 1/467 12345\n"), 0.52);
+    assert_eq!(parse_df("1B-blocks        Avail\n 62725623808 30461173760\n"), Some((62725623808, 30461173760)));
+    assert_eq!(parse_df("1B-blocks Avail\n"), None);
+}
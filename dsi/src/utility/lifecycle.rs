@@ -0,0 +1,160 @@
+use std::time::Instant;
+use rocket::serde::{Deserialize, Serialize};
+use crate::models::buildpack::Buildpack;
+use crate::utility::build_log::BuildEventKind;
+
+/// Marker printed by the CNB lifecycle when a phase starts, i.e. "===> DETECTING"
+const PHASE_MARKER: &str = "===> ";
+
+/// How long a lifecycle phase of the last build took
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PhaseTiming {
+    /// detecting, analyzing, restoring, building or exporting
+    pub phase: String,
+    pub duration_ms: u64,
+}
+
+/// Why a build failed, `code` is machine-readable and `message` is meant for users
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub code: String,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(code: &str, message: &str) -> Failure {
+        Failure { code: code.to_string(), message: message.to_string() }
+    }
+}
+
+/// Files a buildpack looks for during detection, used to tell users what their repo is missing
+fn detection_hint(buildpack_id: &str) -> Option<&'static str> {
+    let name = buildpack_id.rsplit('/').next().unwrap_or_default();
+    match name {
+        "nodejs" | "node-engine" | "npm-install" | "yarn-install" => Some("package.json"),
+        "ruby" | "bundle-install" => Some("Gemfile"),
+        "python" | "pip-install" => Some("requirements.txt"),
+        "go" | "go-dist" => Some("go.mod"),
+        "java" | "maven" => Some("pom.xml"),
+        "php" | "composer" => Some("composer.json"),
+        "nginx" | "httpd" => Some("index.html"),
+        _ => None
+    }
+}
+
+/// Follows the output of `pack build`, timing the lifecycle phases and keeping what is needed to explain a failure.
+#[derive(Debug, Default)]
+pub struct Lifecycle {
+    current: Option<(String, Instant)>,
+    pub phases: Vec<PhaseTiming>,
+    /// Lines mentioning an error, in order
+    errors: Vec<String>,
+}
+
+impl Lifecycle {
+    fn end_phase(&mut self) -> Option<BuildEventKind> {
+        let (phase, started) = self.current.take()?;
+        let duration_ms = started.elapsed().as_millis() as u64;
+        self.phases.push(PhaseTiming { phase: phase.clone(), duration_ms });
+        Some(BuildEventKind::Phase { phase, duration_ms: Some(duration_ms) })
+    }
+
+    /// Feeds one line of output, and returns the phase events it triggers
+    pub fn feed(&mut self, line: &str) -> Vec<BuildEventKind> {
+        let mut events = Vec::new();
+        if let Some(index) = line.find(PHASE_MARKER) {
+            let phase = line[index + PHASE_MARKER.len()..].trim().to_lowercase();
+            if !phase.is_empty() && phase.chars().all(|c| c.is_ascii_alphabetic()) {
+                events.extend(self.end_phase());
+                events.push(BuildEventKind::Phase { phase: phase.clone(), duration_ms: None });
+                self.current = Some((phase, Instant::now()));
+                return events;
+            }
+        }
+        let lower = line.to_lowercase();
+        if lower.contains("error") || lower.contains("no buildpack groups passed detection") {
+            self.errors.push(line.trim().to_string());
+        }
+        events
+    }
+
+    /// Ends the running phase, once the command exited
+    pub fn finish(&mut self) -> Option<BuildEventKind> {
+        self.end_phase()
+    }
+
+    /// Explains a failed build from the output seen so far
+    pub fn failure(&self, buildpacks: &[Buildpack]) -> Failure {
+        let last_phase = match &self.current {
+            Some((phase, _)) => Some(phase.as_str()),
+            None => self.phases.last().map(|p| p.phase.as_str()),
+        };
+        let mentions = |needle: &str| self.errors.iter().any(|e| e.to_lowercase().contains(needle));
+
+        if mentions("no buildpack groups passed detection") {
+            let mut files = buildpacks.iter()
+                .filter_map(|bp| bp.id().ok())
+                .filter_map(|id| detection_hint(&id))
+                .collect::<Vec<&str>>();
+            files.dedup();
+            let message = if files.is_empty() {
+                "None of the buildpacks recognized the app".to_string()
+            } else {
+                format!("None of the buildpacks recognized the app, your repo has no {}", files.join(" or "))
+            };
+            return Failure::new("no_buildpack_detected", &message);
+        }
+        if mentions("run image") || mentions("run-image") {
+            return Failure::new("run_image_missing", "The run image of the stack could not be found or pulled");
+        }
+        let last_error = self.errors.last().cloned().unwrap_or_default();
+        match last_phase {
+            Some("building") => Failure::new("buildpack_failed", &format!("A buildpack failed to build the app: {}", last_error)),
+            Some(phase) => Failure::new(&format!("{}_failed", phase), &format!("The build failed while {}: {}", phase, last_error)),
+            None => Failure::new("build_failed", &format!("The build failed: {}", last_error)),
+        }
+    }
+}
+
+#[test]
+fn test_lifecycle_phases() {
+    println!("Lifecycle markers should produce phase events, and be timed once the next phase starts");
+    let mut lifecycle = Lifecycle::default();
+    assert_eq!(lifecycle.feed("===> DETECTING"), vec![BuildEventKind::Phase { phase: "detecting".to_string(), duration_ms: None }]);
+    assert_eq!(lifecycle.feed("[detector] heroku/nodejs 0.5.0"), vec![]);
+    let events = lifecycle.feed("===> ANALYZING");
+    assert!(matches!(&events[0], BuildEventKind::Phase { phase, duration_ms: Some(_) } if phase == "detecting"));
+    assert!(matches!(&events[1], BuildEventKind::Phase { phase, duration_ms: None } if phase == "analyzing"));
+    lifecycle.finish();
+
+    assert_eq!(lifecycle.phases.iter().map(|p| p.phase.as_str()).collect::<Vec<&str>>(), vec!["detecting", "analyzing"]);
+}
+
+#[test]
+fn test_lifecycle_failures() {
+    let buildpacks = vec![Buildpack { uri: "heroku/nodejs".to_string(), ..Default::default() }];
+
+    println!("A failed detection should tell which file is missing");
+    let mut lifecycle = Lifecycle::default();
+    lifecycle.feed("===> DETECTING");
+    lifecycle.feed("[detector] ======== Results ========");
+    lifecycle.feed("[detector] fail: heroku/nodejs");
+    lifecycle.feed("[detector] ERROR: No buildpack groups passed detection.");
+    lifecycle.finish();
+    assert_eq!(lifecycle.failure(&buildpacks),
+               Failure::new("no_buildpack_detected", "None of the buildpacks recognized the app, your repo has no package.json"));
+
+    println!("A failure while building should be blamed on a buildpack");
+    let mut lifecycle = Lifecycle::default();
+    lifecycle.feed("===> BUILDING");
+    lifecycle.feed("npm ERR! missing script: build");
+    lifecycle.feed("ERROR: failed to build: exit status 1");
+    assert_eq!(lifecycle.failure(&buildpacks).code, "buildpack_failed");
+
+    println!("A missing run image should be recognized");
+    let mut lifecycle = Lifecycle::default();
+    lifecycle.feed("===> ANALYZING");
+    lifecycle.feed("ERROR: failed to build: failed to fetch run image: heroku/pack:20: not found");
+    assert_eq!(lifecycle.failure(&buildpacks).code, "run_image_missing");
+}
@@ -1 +1,15 @@
-pub mod buildpack;
\ No newline at end of file
+pub mod archive;
+pub mod build_log;
+pub mod buildpack;
+pub mod dams_client;
+pub mod docker;
+pub mod host;
+pub mod lifecycle;
+pub mod nginx;
+pub mod proxy;
+pub mod pipeline;
+pub mod reconciler;
+pub mod router_client;
+pub mod secrets;
+pub mod snooze;
+pub mod store;
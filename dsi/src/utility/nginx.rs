@@ -0,0 +1,158 @@
+use std::sync::Arc;
+use app_address::AppAddress;
+use command_runner::CommandRunner;
+use command_runner::nginx::NginxConfig;
+use crate::config::DsiConfig;
+use crate::models::droid_state::{DroidState, DroidStatus};
+use crate::utility::store::DroidStore;
+
+/// Served in place of a snoozed droid
+const SNOOZED_PAGE: &str = "This app is snoozing, retry in a few seconds.";
+
+/// Local nginx routing, rendered from the droid store. Each routable droid gets a server block for its
+/// `<uid>.<port>.<server_id>.<domain>` addresses, any other host gets a 404.
+#[derive(Clone)]
+pub struct Nginx {
+    /// File included by nginx, i.e. "/etc/nginx/sites-available/droid-server"
+    pub config: NginxConfig,
+    pub server_id: String,
+    pub domains: Vec<String>,
+    /// DNS server resolving the droid container names
+    pub resolver: String,
+}
+
+fn server_block(state: &DroidState, addresses: &[AppAddress]) -> String {
+    let location = match state.status {
+        DroidStatus::Snoozed => format!(r#"        add_header Retry-After 10 always;
+        default_type text/plain;
+        return 503 "{}\n";"#, SNOOZED_PAGE),
+        _ => format!(r#"        set $droid http://{};
+        proxy_pass $droid;
+        proxy_http_version 1.1;
+        proxy_set_header Upgrade $http_upgrade;
+        proxy_set_header Connection $connection_upgrade;
+        proxy_set_header Host $host;
+        proxy_set_header X-Real-IP $remote_addr;
+        proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+        proxy_set_header X-Forwarded-Proto $scheme;"#, addresses[0].upstream()),
+    };
+    let server_names = addresses.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(" ");
+    format!(r#"
+# droid {} ({:?})
+server {{
+    listen 80;
+    server_name {};
+
+    location / {{
+{}
+    }}
+}}
+"#, state.app_id, state.status, server_names, location)
+}
+
+/// Renders the nginx config routing to the droids. Running droids, and droids being rebuilt, are proxied to
+/// (WebSockets included), snoozed droids get a 503 page, and droids without a port or a container are left out.
+pub fn render(states: &[DroidState], server_id: &str, domains: &[String], resolver: &str) -> String {
+    let mut config = format!(r#"# Generated by the DSI from the droid states, changes will be overwritten.
+map $http_upgrade $connection_upgrade {{
+    default upgrade;
+    ''      close;
+}}
+
+resolver {} valid=10s;
+
+server {{
+    listen 80 default_server;
+    server_name _;
+    return 404;
+}}
+"#, resolver);
+
+    for state in states {
+        let routable = match state.status {
+            // a droid being rebuilt is served by its previous container until the new one is deployed
+            DroidStatus::Running | DroidStatus::Building | DroidStatus::Built => state.container.is_some(),
+            DroidStatus::Snoozed => true,
+            _ => false
+        };
+        let addresses = state.addresses(server_id, domains);
+        if routable && !addresses.is_empty() {
+            config.push_str(&server_block(state, &addresses));
+        }
+    }
+    config
+}
+
+impl Nginx {
+    pub fn new(config: &DsiConfig, runner: Arc<dyn CommandRunner>) -> Nginx {
+        Nginx {
+            config: NginxConfig::new(&config.nginx_config, "nginx", runner),
+            server_id: config.server_id.clone(),
+            domains: config.domains.clone(),
+            resolver: config.nginx_resolver.clone(),
+        }
+    }
+
+    /// Renders the config from the droid store and, if it changed, validates it with `nginx -t` and reloads nginx.
+    /// An invalid config is rolled back so that nginx keeps serving the previous one.
+    pub async fn sync(&self, store: &DroidStore) -> Result<(), String> {
+        self.config.sync(|| render(&store.all(), &self.server_id, &self.domains, &self.resolver)).await
+    }
+}
+
+#[test]
+fn test_render() {
+    println!("Running, rebuilt and snoozed droids with a port should be routed, the others should get the default 404");
+    let droid = |app_id: i64, status: DroidStatus, port: Option<u16>| {
+        let mut state = DroidState::new(app_id, &format!("droid{}", app_id));
+        state.status = status;
+        state.port = port;
+        state.container = Some(state.uid.clone());
+        state
+    };
+    let states = vec![
+        droid(1, DroidStatus::Running, Some(7000)),
+        droid(2, DroidStatus::Snoozed, Some(3000)),
+        droid(3, DroidStatus::Running, None),
+        droid(4, DroidStatus::Failed, Some(80)),
+        droid(5, DroidStatus::Building, Some(5000)),
+    ];
+    let config = render(&states, "ds1", &["appoxy.com".to_string(), "localhost".to_string()], "127.0.0.11");
+
+    assert!(config.contains("listen 80 default_server;\n    server_name _;\n    return 404;"));
+    assert!(config.contains("server_name droid1.7000.ds1.appoxy.com droid1.7000.ds1.localhost;"));
+    assert!(config.contains("set $droid http://droid1:7000;"));
+    assert!(config.contains("proxy_set_header Connection $connection_upgrade;"));
+    assert!(config.contains("server_name droid2.3000.ds1.appoxy.com droid2.3000.ds1.localhost;"));
+    assert!(config.contains("return 503"));
+    assert!(!config.contains("server_name droid3."));
+    assert!(!config.contains("server_name droid4."));
+    assert!(config.contains("set $droid http://droid5:5000;"));
+}
+
+#[test]
+fn test_sync_rollback() {
+    println!("A config failing `nginx -t` should be rolled back and nginx should not be reloaded");
+    let dir = std::env::temp_dir().join(format!("dsi-nginx-rollback-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let dumps_dir = dir.to_str().unwrap().to_string();
+    let store = DroidStore::load(&dumps_dir).unwrap();
+    let mut state = DroidState::new(1, "droid1");
+    state.status = DroidStatus::Snoozed;
+    state.port = Some(80);
+    store.save(state).unwrap();
+
+    let config_path = format!("{}/nginx.conf", dumps_dir);
+    std::fs::write(&config_path, "# previous config\n").unwrap();
+    let runner = Arc::new(crate::test_support::ScriptedRunner::new(vec![
+        crate::test_support::Script::fail("nginx -t", 1, "nginx: [emerg] unknown directive\n"),
+    ]));
+    let config = DsiConfig { nginx_config: config_path.clone(), ..Default::default() };
+    let nginx = Nginx::new(&config, runner.clone());
+
+    let result = tokio_test::block_on(nginx.sync(&store));
+    assert_eq!(result, Err("nginx -t exited with code 1: nginx: [emerg] unknown directive".to_string()));
+    assert_eq!(std::fs::read_to_string(&config_path).unwrap(), "# previous config\n");
+    assert_eq!(runner.calls().iter().map(|c| c.line()).collect::<Vec<String>>(), vec!["nginx -t"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,678 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::OwnedMutexGuard;
+use command_runner::{CommandRunner, CommandSpec};
+use crate::models::builder::Builder;
+use crate::config::DsiConfig;
+use crate::models::detection;
+use crate::models::droid::Droid;
+use crate::models::env;
+use crate::models::git_credentials::{self, GitCredentials};
+use crate::models::manifest::{AppConfig, Manifest, MANIFEST};
+use crate::models::project_descriptor::{ProjectDescriptor, PROJECT_DESCRIPTOR};
+use crate::models::droid_state::DroidStatus;
+use crate::utility::archive::{self, ArchiveFormat};
+use crate::utility::build_log::{BuildEventKind, BuildLog};
+use crate::utility::docker::{ContainerSpec, DockerClient, EndpointConfig, HostConfig, NetworkingConfig, DROID_LABEL};
+use crate::utility::lifecycle::{Failure, Lifecycle};
+use crate::utility::nginx::Nginx;
+use crate::utility::router_client::RouterClient;
+use crate::utility::secrets::SecretBox;
+use crate::utility::store::DroidStore;
+
+/// Builds and deploys droids: clones the repo, creates the builder, builds the image with `pack`
+/// and (re)creates the droid container.
+#[derive(Clone)]
+pub struct Pipeline {
+    pub store: DroidStore,
+    pub docker: DockerClient,
+    pub runner: Arc<dyn CommandRunner>,
+    /// Docker network the droid containers are attached to
+    pub network: String,
+    /// Routes to the droid once it is deployed
+    pub nginx: Nginx,
+    /// Announces the droid to the global router once it is deployed
+    pub router: RouterClient,
+    /// Decrypts the secrets of the droids when their container is created
+    pub secrets: SecretBox,
+    /// Registry, default port and resource limits the manifests of the droids are merged with
+    pub config: DsiConfig,
+    /// Locks of the repo clones, shared by the droids built from the same repo and branch
+    pub sources: SourceLocks,
+    /// Builds running in this DSI process
+    pub builds: ActiveBuilds,
+}
+
+/// Where the source of a build comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A clone of the repo and branch of the droid
+    Git,
+    /// An uploaded archive, extracted to `<dumps_dir>/<app_id>/upload`
+    Archive { path: String, format: ArchiveFormat, digest: String },
+}
+
+/// One lock per clone directory, held from the clone until `pack build` is done with the source, so that the apps
+/// of a monorepo can share a clone without a build seeing another one re-clone it
+#[derive(Clone, Default)]
+pub struct SourceLocks(Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>);
+
+impl SourceLocks {
+    pub async fn lock(&self, dir: &str) -> OwnedMutexGuard<()> {
+        let lock = self.0.lock().unwrap().entry(dir.to_string()).or_default().clone();
+        lock.lock_owned().await
+    }
+}
+
+/// App ids of the droids being built by this DSI process. The reconciler fails the other Building and Built droids,
+/// their build having been interrupted.
+#[derive(Clone, Default)]
+pub struct ActiveBuilds(Arc<Mutex<HashSet<i64>>>);
+
+impl ActiveBuilds {
+    /// Marks the droid as being built until the returned guard is dropped. None if it already is.
+    pub fn start(&self, app_id: i64) -> Option<ActiveBuild> {
+        if !self.0.lock().unwrap().insert(app_id) {
+            return None;
+        }
+        Some(ActiveBuild { builds: self.clone(), app_id })
+    }
+
+    pub fn contains(&self, app_id: i64) -> bool {
+        self.0.lock().unwrap().contains(&app_id)
+    }
+
+    pub fn all(&self) -> Vec<i64> {
+        self.0.lock().unwrap().iter().copied().collect()
+    }
+}
+
+/// A build of `ActiveBuilds`, which ends when it is dropped
+pub struct ActiveBuild {
+    builds: ActiveBuilds,
+    app_id: i64,
+}
+
+impl Drop for ActiveBuild {
+    fn drop(&mut self) {
+        self.builds.0.lock().unwrap().remove(&self.app_id);
+    }
+}
+
+/// Directory of the dumps holding the repo mirrors and checkouts, shared by the droids
+pub const SOURCES_DIR: &str = "sources";
+
+/// `name` with everything but letters, digits, `.`, `-` and `@` replaced by `_`
+fn dir_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '@' { c } else { '_' }).collect()
+}
+
+/// Turns "github.com/user/repo" into a clonable url, urls and scp-like ssh addresses are kept as is.
+fn clone_url(repo: &str) -> String {
+    if repo.contains("://") || repo.starts_with("git@") {
+        repo.to_string()
+    } else {
+        format!("https://{}", repo)
+    }
+}
+
+/// Turns the repo into an scp-like ssh address for deploy keys: "github.com/user/repo" and
+/// "https://github.com/user/repo" become "git@github.com:user/repo", ssh addresses are kept as is.
+fn ssh_url(repo: &str) -> String {
+    if repo.starts_with("git@") || repo.starts_with("ssh://") {
+        return repo.to_string();
+    }
+    let repo = repo.split_once("://").map(|(_, rest)| rest).unwrap_or(repo);
+    match repo.split_once('/') {
+        Some((host, path)) => format!("git@{}:{}", host, path),
+        None => format!("git@{}", repo),
+    }
+}
+
+/// Turns the repo into an https url for tokens, the reverse of `ssh_url`
+fn https_url(repo: &str) -> String {
+    match repo.strip_prefix("git@").and_then(|rest| rest.split_once(':')) {
+        Some((host, path)) => format!("https://{}/{}", host, path),
+        None => clone_url(repo),
+    }
+}
+
+/// Credential helper handing the token of `APPOXY_GIT_USERNAME` and `APPOXY_GIT_TOKEN` to git, so that the token is
+/// never part of a command line
+const TOKEN_HELPER: &str = r#"!f() { test "$1" = get || return 0; echo "username=${APPOXY_GIT_USERNAME}"; echo "password=${APPOXY_GIT_TOKEN}"; }; f"#;
+
+/// Digest of what the image of the droid is built from: its source, builder, `root_dir` and environments. The values
+/// of the secrets are left out, they only reach the container.
+fn build_digest(source_id: &str, builder: &Builder, droid: &Droid) -> String {
+    let mut hasher = Sha256::new();
+    let mut env = droid.redacted_env();
+    env.sort();
+    let mut build_env = droid.build_env.clone();
+    build_env.sort();
+    let parts = [
+        source_id.to_string(),
+        toml::to_string(builder).unwrap_or_default(),
+        droid.root_dir.clone().unwrap_or_default(),
+        env.join("\n"),
+        build_env.join("\n"),
+    ];
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("sha256:{:x}", hasher.finalize())
+}
+
+/// The directory of the clone the app is built from: `root_dir`, which must exist and stay inside the clone once
+/// its symlinks are followed
+fn app_dir(clone: &str, root_dir: Option<&str>) -> Result<String, String> {
+    let root_dir = match root_dir {
+        Some(root_dir) => root_dir,
+        None => return Ok(clone.to_string()),
+    };
+    let path = Path::new(clone).join(root_dir);
+    let resolved = path.canonicalize().map_err(|_| format!("root_dir {} does not exist in the repo", root_dir))?;
+    let clone = Path::new(clone).canonicalize().map_err(|e| e.to_string())?;
+    if !resolved.starts_with(&clone) {
+        return Err(format!("root_dir {} leaves the repo", root_dir));
+    }
+    if !resolved.is_dir() {
+        return Err(format!("root_dir {} is not a directory", root_dir));
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
+impl Pipeline {
+    /// Where `commit` of `repo` is checked out, shared by all the droids built from it
+    pub fn source_dir(&self, repo: &str, commit: &str) -> String {
+        format!("{}/{}/{}", self.store.dumps_dir(), SOURCES_DIR, dir_name(&format!("{}@{}", repo, commit)))
+    }
+
+    /// Where the bare mirror of the repo reached at `url` is cached, fetched again by every build
+    pub fn mirror_dir(&self, url: &str) -> String {
+        format!("{}/{}/mirrors/{}", self.store.dumps_dir(), SOURCES_DIR, dir_name(url))
+    }
+
+    /// Runs one command, logging its stdout and stderr lines and exit code, and recording its pid as the build process.
+    /// Fails with the last line of stderr if the command exits with a non-zero code.
+    /// Every line is also passed to `on_line`.
+    async fn step(&self, app_id: i64, command: CommandSpec, log: &BuildLog, on_line: &(dyn Fn(&str) + Sync)) -> Result<(), String> {
+        println!("Droid {}: running {}", app_id, command.line());
+        let mut process = match self.runner.spawn(&command) {
+            Ok(process) => process,
+            Err(err) => return Err(format!("Error running {}: {}", command.program, err))
+        };
+        self.store.update(app_id, |s| s.build_pid = process.pid);
+
+        let mut last_error = String::new();
+        let stdout = async {
+            let mut lines = BufReader::new(&mut process.stdout).lines();
+            while let Some(line) = lines.next_line().await? {
+                on_line(&line);
+                log.push(BuildEventKind::Stdout { line });
+            }
+            Ok::<(), std::io::Error>(())
+        };
+        let stderr = async {
+            let mut lines = BufReader::new(&mut process.stderr).lines();
+            while let Some(line) = lines.next_line().await? {
+                on_line(&line);
+                if !line.trim().is_empty() {
+                    last_error = line.trim().to_string();
+                }
+                log.push(BuildEventKind::Stderr { line });
+            }
+            Ok::<(), std::io::Error>(())
+        };
+        let (out, err) = tokio::join!(stdout, stderr);
+        if let Err(err) = out.and(err) {
+            return Err(format!("Error reading output of {}: {}", command.program, err));
+        }
+
+        let code = match process.exit.await {
+            Ok(code) => code,
+            Err(err) => return Err(format!("Error waiting for {}: {}", command.program, err))
+        };
+        log.push(BuildEventKind::Exit { code });
+        match code {
+            0 => Ok(()),
+            code => Err(format!("{} exited with code {}: {}", command.program, code, last_error)),
+        }
+    }
+
+    /// Completes the droid with the `appoxy.toml` and then the `project.toml` of its repo, if any, detects its
+    /// buildpacks if it still has none, and records the effective config. The builder is created again if the
+    /// buildpacks or the stack came from any of them. Returns the descriptor to pass to `pack build`: the one of the
+    /// repo, or a copy without its buildpacks if the droid declares its own.
+    async fn apply_manifest(&self, droid: &mut Droid, builder: &mut Builder, source: &str, log: &BuildLog) -> Result<Option<String>, Failure> {
+        let invalid = |e: String| Failure::new("invalid_manifest", &e);
+        let manifest = Manifest::load(source).map_err(invalid)?;
+        let descriptor = ProjectDescriptor::load(source).map_err(invalid)?;
+        if manifest.is_none() && descriptor.is_none() && !droid.buildpacks.is_empty() {
+            return Ok(None);
+        }
+        let mut builder_changed = false;
+        if let Some(manifest) = &manifest {
+            println!("Droid {}: applying {}", droid.app_id, MANIFEST);
+            builder_changed |= manifest.merge_into(droid);
+        }
+        let mut descriptor_path = None;
+        if let Some(descriptor) = &descriptor {
+            println!("Droid {}: applying {}", droid.app_id, PROJECT_DESCRIPTOR);
+            if !droid.buildpacks.is_empty() && !descriptor.buildpacks.is_empty() {
+                let warning = format!("Warning: ignoring the buildpacks of {}, the droid declares its own", PROJECT_DESCRIPTOR);
+                println!("Droid {}: {}", droid.app_id, warning);
+                log.push(BuildEventKind::Stderr { line: warning });
+                let dir = format!("{}/{}", self.store.dumps_dir(), droid.app_id);
+                std::fs::create_dir_all(&dir).map_err(|e| invalid(e.to_string()))?;
+                let path = format!("{}/{}", dir, PROJECT_DESCRIPTOR);
+                std::fs::write(&path, descriptor.without_buildpacks()).map_err(|e| invalid(e.to_string()))?;
+                descriptor_path = Some(path);
+            } else {
+                descriptor_path = Some(format!("{}/{}", source, PROJECT_DESCRIPTOR));
+            }
+            builder_changed |= descriptor.merge_into(droid);
+        }
+        if droid.buildpacks.is_empty() {
+            let plan = detection::plan(droid, source, &self.config.registry_url, &self.config.stacks).await
+                .map_err(|e| Failure::new("detection_failed", &e))?;
+            println!("Droid {}: detected {:?} on {}", droid.app_id, plan.buildpacks, plan.stack);
+            log.push(BuildEventKind::Plan { plan });
+            builder_changed = true;
+        }
+        if builder_changed {
+            droid.detect_common_stacks(&self.config.registry_url).await
+                .map_err(|e| Failure::new("detection_failed", &e.to_string()))?;
+            *builder = droid.create_builder().await.map_err(invalid)?;
+            builder.save(self.store.dumps_dir(), droid.app_id.to_string()).map_err(|e| invalid(e.to_string()))?;
+        }
+        let (mut env, build_env, port) = droid.parse_env(self.config.default_port).map_err(invalid)?;
+        let resources = droid.resources.resolve(&self.config.default_resources, &self.config.max_resources).map_err(invalid)?;
+        let secrets: BTreeSet<String> = droid.secrets.iter().cloned().collect();
+        for key in &secrets {
+            if let Some(value) = env.get_mut(key) {
+                *value = self.secrets.encrypt(value);
+            }
+        }
+        let mut config = AppConfig::of(droid, manifest.as_ref());
+        config.project_descriptor = descriptor.is_some();
+        let image = builder.image(droid.app_id);
+        self.store.update(droid.app_id, |s| {
+            s.env = env;
+            s.build_env = build_env;
+            s.secrets = secrets;
+            s.port = Some(port);
+            s.resources = resources;
+            s.config = config;
+            s.builder = Some(image);
+        });
+        Ok(descriptor_path)
+    }
+
+    /// Checks the repo of the droid out or extracts its uploaded archive, and returns where, with the id of the source
+    /// (its commit SHA or archive digest). The source is locked until the returned guard is dropped.
+    async fn fetch(&self, droid: &Droid, source: &Source, log: &BuildLog) -> Result<(String, OwnedMutexGuard<()>, String), Failure> {
+        match source {
+            Source::Git => {
+                log.push(BuildEventKind::Stage { stage: "clone".to_string() });
+                let (git, url, key) = self.git(droid).map_err(|e| Failure::new("clone_failed", &e))?;
+                let result = self.checkout(droid, &git, &url, log).await;
+                if let Some(key) = key {
+                    let _ = std::fs::remove_file(key);
+                }
+                result
+            }
+            Source::Archive { path, format, digest } => {
+                let dir = format!("{}/{}/upload", self.store.dumps_dir(), droid.app_id);
+                let lock = self.sources.lock(&dir).await;
+                let _ = std::fs::remove_dir_all(&dir);
+                std::fs::create_dir_all(&dir).map_err(|e| Failure::new("extraction_failed", &e.to_string()))?;
+                log.push(BuildEventKind::Stage { stage: "extract".to_string() });
+                self.step(droid.app_id, format.extract_command(path, &dir), log, &|_| {}).await
+                    .map_err(|e| Failure::new("extraction_failed", &e))?;
+                archive::check_symlinks(&dir).map_err(|e| Failure::new("unsafe_archive", &e))?;
+                Ok((dir, lock, digest.clone()))
+            }
+        }
+    }
+
+    /// `git` authenticated with the git credentials of the droid if it has any, and the url of its repo to use with
+    /// them. A deploy key is decrypted to a file readable by the DSI only, whose path is returned to be removed once
+    /// the source is fetched. A token is handed to git through the environment, which is left out of the build log.
+    fn git(&self, droid: &Droid) -> Result<(CommandSpec, String, Option<String>), String> {
+        let git = CommandSpec::new("git").env("GIT_TERMINAL_PROMPT", "0");
+        match GitCredentials::load(self.store.dumps_dir(), droid.app_id)? {
+            None => Ok((git, clone_url(&droid.repo), None)),
+            Some(GitCredentials::DeployKey { private_key, .. }) => {
+                let key = format!("{}/{}/deploy_key", self.store.dumps_dir(), droid.app_id);
+                git_credentials::write_private(&key, &self.secrets.decrypt(&private_key)?)?;
+                let ssh = format!("ssh -i {} -o IdentitiesOnly=yes -o BatchMode=yes -o StrictHostKeyChecking=accept-new", key);
+                Ok((git.env("GIT_SSH_COMMAND", &ssh), ssh_url(&droid.repo), Some(key)))
+            }
+            Some(GitCredentials::Token { username, token }) => {
+                let git = git
+                    .env("APPOXY_GIT_USERNAME", &username)
+                    .env("APPOXY_GIT_TOKEN", &self.secrets.decrypt(&token)?)
+                    .arg("-c").arg("credential.helper=")
+                    .arg("-c").arg(format!("credential.helper={}", TOKEN_HELPER));
+                Ok((git, https_url(&droid.repo), None))
+            }
+        }
+    }
+
+    /// Fetches the repo into its mirror (cloning it on the first build), resolves the commit of the droid (the head of
+    /// its branch if it pins none) and checks it out with its submodules and Git LFS files. Returns the checkout, its
+    /// lock and the commit SHA.
+    async fn checkout(&self, droid: &Droid, git: &CommandSpec, url: &str, log: &BuildLog) -> Result<(String, OwnedMutexGuard<()>, String), Failure> {
+        let clone_failed = |e: String| Failure::new("clone_failed", &e);
+        let mirror = self.mirror_dir(url);
+        let mirror_lock = self.sources.lock(&mirror).await;
+        let command = if Path::new(&mirror).join("HEAD").is_file() {
+            git.clone().arg("-C").arg(&mirror).arg("fetch").arg("--prune").arg("origin")
+        } else {
+            let _ = std::fs::remove_dir_all(&mirror);
+            git.clone().arg("clone").arg("--mirror").arg(url).arg(&mirror)
+        };
+        self.step(droid.app_id, command, log, &|_| {}).await.map_err(clone_failed)?;
+
+        let (revision, missing) = match &droid.commit {
+            Some(commit) => (commit.clone(), format!("Commit {} not found in {}", commit, droid.repo)),
+            None => (format!("refs/heads/{}", droid.branch), format!("Branch {} not found in {}", droid.branch, droid.repo)),
+        };
+        let resolve = CommandSpec::new("git")
+            .arg("-C").arg(&mirror)
+            .arg("rev-parse").arg("--verify").arg("--quiet").arg("--end-of-options")
+            .arg(format!("{}^{{commit}}", revision));
+        let commit = command_runner::output(self.runner.as_ref(), &resolve).await.ok()
+            .filter(|output| output.code == 0)
+            .map(|output| output.stdout.trim().to_string())
+            .filter(|commit| !commit.is_empty())
+            .ok_or_else(|| Failure::new("revision_not_found", &missing))?;
+        println!("Droid {}: checking out {}", droid.app_id, commit);
+        log.push(BuildEventKind::Stdout { line: format!("Checking out {}", commit) });
+        if Path::new(&mirror).join("worktrees").is_dir() {
+            // forget the checkouts removed since the last build
+            let _ = command_runner::output(self.runner.as_ref(), &CommandSpec::new("git").arg("-C").arg(&mirror).arg("worktree").arg("prune")).await;
+        }
+        drop(mirror_lock);
+
+        let checkout = self.source_dir(&droid.repo, &commit);
+        let lock = self.sources.lock(&checkout).await;
+        let _ = std::fs::remove_dir_all(&checkout);
+        // LFS files are pulled below, with the credentials
+        self.step(droid.app_id, CommandSpec::new("git")
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
+            .arg("-C").arg(&mirror)
+            .arg("worktree").arg("add").arg("--force").arg("--detach")
+            .arg(&checkout)
+            .arg(&commit), log, &|_| {}).await
+            .map_err(clone_failed)?;
+        if Path::new(&checkout).join(".gitmodules").is_file() {
+            self.step(droid.app_id, git.clone()
+                .arg("-C").arg(&checkout)
+                .arg("submodule").arg("update").arg("--init").arg("--recursive"), log, &|_| {}).await
+                .map_err(clone_failed)?;
+        }
+        let attributes = std::fs::read_to_string(Path::new(&checkout).join(".gitattributes")).unwrap_or_default();
+        if attributes.contains("filter=lfs") {
+            self.step(droid.app_id, git.clone().arg("-C").arg(&checkout).arg("lfs").arg("pull"), log, &|_| {}).await
+                .map_err(clone_failed)?;
+        }
+        Ok((checkout, lock, commit))
+    }
+
+    /// Whether docker still has the image
+    async fn has_image(&self, image: &str) -> bool {
+        match self.docker.list_images().await {
+            Ok(images) => images.iter().any(|i| i.repo_tags.iter().any(|tag| tag == image)),
+            Err(err) => {
+                println!("Error listing images: {}", err);
+                false
+            }
+        }
+    }
+
+    /// Builds the image of the droid, unless the source, builder and environment are the ones of its current image
+    async fn build(&self, droid: &mut Droid, builder: &mut Builder, source: &Source, log: &BuildLog) -> Result<String, Failure> {
+        let (clone, _source_lock, source_id) = self.fetch(droid, source, log).await?;
+        let result = self.build_from(droid, builder, &clone, &source_id, log).await;
+        if *source == Source::Git {
+            // the checkout is cheap to recreate from the mirror
+            let _ = std::fs::remove_dir_all(&clone);
+        }
+        result
+    }
+
+    async fn build_from(&self, droid: &mut Droid, builder: &mut Builder, clone: &str, source_id: &str, log: &BuildLog) -> Result<String, Failure> {
+        let source = app_dir(clone, droid.root_dir.as_deref()).map_err(|e| Failure::new("invalid_root_dir", &e))?;
+        let descriptor = self.apply_manifest(droid, builder, &source, log).await?;
+        if droid.stack.id.is_empty() {
+            return Err(Failure::new("no_stack", &format!("No stack in the request or the {} of the repo", MANIFEST)));
+        }
+        let digest = build_digest(source_id, builder, droid);
+        let previous = self.store.get(droid.app_id).filter(|s| s.build_digest.as_ref() == Some(&digest)).and_then(|s| s.image);
+        if let Some(image) = previous {
+            if self.has_image(&image).await {
+                let line = "Source, builder and environment unchanged since the last build, restarting".to_string();
+                println!("Droid {}: {}", droid.app_id, line);
+                log.push(BuildEventKind::Stdout { line });
+                self.store.update(droid.app_id, |s| {
+                    s.status = DroidStatus::Built;
+                    s.source = Some(source_id.to_string());
+                });
+                return Ok(image);
+            }
+        }
+
+        log.push(BuildEventKind::Stage { stage: "builder".to_string() });
+        self.step(droid.app_id, builder.create_command(self.store.dumps_dir(), droid.app_id), log, &|_| {}).await
+            .map_err(|e| Failure::new("builder_failed", &e))?;
+
+        let image = format!("{}:latest", droid.app_id);
+        log.push(BuildEventKind::Stage { stage: "build".to_string() });
+        let lifecycle = Mutex::new(Lifecycle::default());
+        let mut command = CommandSpec::new("pack")
+            .arg("build").arg(&image)
+            .arg("--builder").arg(builder.image(droid.app_id))
+            .arg("--path").arg(&source);
+        if let Some(descriptor) = &descriptor {
+            command = command.arg("--descriptor").arg(descriptor);
+        }
+        for var in &droid.build_env {
+            command = command.arg("--env").arg(var);
+        }
+        let result = self.step(droid.app_id, command, log, &|line| {
+            for event in lifecycle.lock().unwrap().feed(line) {
+                log.push(event);
+            }
+        }).await;
+
+        let mut lifecycle = lifecycle.into_inner().unwrap();
+        if let Some(event) = lifecycle.finish() {
+            log.push(event);
+        }
+        let phases = lifecycle.phases.clone();
+        self.store.update(droid.app_id, |s| s.phases = phases);
+        if let Err(err) = result {
+            println!("Droid {}: {}", droid.app_id, err);
+            return Err(lifecycle.failure(&droid.buildpacks));
+        }
+        self.store.update(droid.app_id, |s| {
+            s.status = DroidStatus::Built;
+            s.image = Some(image.clone());
+            s.source = Some(source_id.to_string());
+            s.build_digest = Some(digest);
+        });
+
+        Ok(image)
+    }
+
+    /// Replaces the droid container with a new one running `image`, and returns the container name
+    async fn deploy(&self, app_id: i64, image: &str) -> Result<String, String> {
+        let name = self.create_container(app_id, image).await?;
+        self.docker.start_container(&name).await?;
+        Ok(name)
+    }
+
+    /// Creates the droid network if it does not exist yet, so that containers are never created detached from nginx
+    async fn ensure_network(&self) -> Result<(), String> {
+        let networks = self.docker.list_networks().await
+            .map_err(|e| format!("Error listing the docker networks: {}", e.message))?;
+        if !networks.iter().any(|n| n.name == self.network) {
+            println!("Creating the docker network {}", self.network);
+            self.docker.create_network(&self.network).await
+                .map_err(|e| format!("Error creating the docker network {}: {}", self.network, e.message))?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the droid container with a new, stopped one running `image` with the recorded environment and
+    /// resource limits, and returns the container name
+    async fn create_container(&self, app_id: i64, image: &str) -> Result<String, String> {
+        let state = self.store.get(app_id).ok_or("Droid state is missing")?;
+        let name = state.uid;
+        app_address::validate_uid(&name).map_err(|e| e.to_string())?;
+        self.ensure_network().await?;
+        let (nano_cpus, memory, memory_swap, pids_limit) = state.resources.docker_limits();
+        if let Err(err) = self.docker.remove_container(&name).await {
+            if !err.is_not_found() {
+                return Err(err.into());
+            }
+        }
+        let spec = ContainerSpec {
+            image: image.to_string(),
+            env: env::to_list(&self.secrets.open(&state.env, &state.secrets)?),
+            labels: HashMap::from([(DROID_LABEL.to_string(), app_id.to_string())]),
+            host_config: HostConfig {
+                network_mode: Some(self.network.clone()),
+                nano_cpus,
+                memory,
+                memory_swap,
+                pids_limit,
+            },
+            networking_config: Some(NetworkingConfig {
+                endpoints_config: HashMap::from([(self.network.clone(), EndpointConfig { aliases: vec![name.clone()] })]),
+            }),
+        };
+        self.docker.create_container(&name, &spec).await?;
+        Ok(name)
+    }
+
+    /// Recreates the droid container from its current image after its environment changed, without rebuilding it.
+    /// The container is only started if the droid was running. The droid is routed again if its port changed.
+    pub async fn apply_env(&self, app_id: i64, previous_port: Option<u16>) -> Result<(), String> {
+        let state = self.store.get(app_id).ok_or("Droid state is missing")?;
+        if let Some(image) = &state.image {
+            let name = self.create_container(app_id, image).await?;
+            if state.status == DroidStatus::Running {
+                self.docker.start_container(&name).await?;
+            }
+            self.store.update(app_id, |s| s.container = Some(name));
+        }
+        if state.port != previous_port {
+            if let Err(err) = self.nginx.sync(&self.store).await {
+                println!("Error routing droid {}: {}", app_id, err);
+            }
+            if state.container.is_some() {
+                self.router.report("moved", &state).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds and deploys the droid, recording the outcome in the droid store and the events in `log`. `build` ends
+    /// before the log does, so that its subscribers can build the droid again.
+    pub async fn run(&self, mut droid: Droid, mut builder: Builder, source: Source, log: Arc<BuildLog>, build: ActiveBuild) {
+        let result = match self.build(&mut droid, &mut builder, &source, &log).await {
+            Ok(image) => {
+                log.push(BuildEventKind::Stage { stage: "deploy".to_string() });
+                self.deploy(droid.app_id, &image).await.map_err(|e| Failure::new("deploy_failed", &e))
+            }
+            Err(err) => Err(err)
+        };
+        // a failed rebuild leaves the previous container of the droid in place, which keeps serving it
+        let serving = match (&result, self.store.get(droid.app_id).and_then(|s| s.container)) {
+            (Err(failure), Some(container)) if failure.code != "deploy_failed" =>
+                self.docker.inspect_container(&container).await.is_ok_and(|c| c.state.running),
+            _ => false
+        };
+
+        self.store.update(droid.app_id, |s| match &result {
+            Ok(container) => {
+                s.status = DroidStatus::Running;
+                s.container = Some(container.clone());
+                s.build_pid = None;
+                s.error_code = None;
+                s.error = None;
+            }
+            Err(failure) => {
+                s.fail(&failure.code, &failure.message);
+                if serving {
+                    s.status = DroidStatus::Running;
+                }
+            }
+        });
+        match result {
+            Ok(_) => {
+                println!("Droid {} deployed", droid.app_id);
+                if let Err(err) = self.nginx.sync(&self.store).await {
+                    println!("Error routing droid {}: {}", droid.app_id, err);
+                }
+                if let Some(state) = self.store.get(droid.app_id) {
+                    self.router.report("created", &state).await;
+                }
+            }
+            Err(failure) => {
+                println!("Droid {} failed ({}): {}", droid.app_id, failure.code, failure.message);
+                log.push(BuildEventKind::Error { code: failure.code, message: failure.message });
+            }
+        }
+        drop(build);
+        log.finish();
+    }
+}
+
+#[test]
+fn test_app_dir() {
+    println!("root_dir should resolve inside the clone, symlinks leaving it being rejected");
+    let clone = std::env::temp_dir().join(format!("dsi-app-dir-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&clone);
+    std::fs::create_dir_all(clone.join("apps/web")).unwrap();
+    std::os::unix::fs::symlink("/etc", clone.join("apps/escape")).unwrap();
+    std::os::unix::fs::symlink("web", clone.join("apps/site")).unwrap();
+    let clone_str = clone.to_str().unwrap();
+    assert_eq!(app_dir(clone_str, None), Ok(clone_str.to_string()));
+    assert_eq!(app_dir(clone_str, Some("apps/web")), Ok(format!("{}/apps/web", clone_str)));
+    assert!(app_dir(clone_str, Some("apps/site")).is_ok());
+    assert_eq!(app_dir(clone_str, Some("apps/escape")), Err("root_dir apps/escape leaves the repo".to_string()));
+    assert_eq!(app_dir(clone_str, Some("apps/api")), Err("root_dir apps/api does not exist in the repo".to_string()));
+    std::fs::remove_dir_all(&clone).unwrap();
+}
+
+#[test]
+fn test_clone_urls() {
+    println!("Repos should be turned into ssh addresses for deploy keys and https urls for tokens");
+    assert_eq!(ssh_url("github.com/user/repo"), "git@github.com:user/repo");
+    assert_eq!(ssh_url("https://gitlab.com/group/sub/repo.git"), "git@gitlab.com:group/sub/repo.git");
+    assert_eq!(ssh_url("git@github.com:user/repo"), "git@github.com:user/repo");
+    assert_eq!(https_url("git@github.com:user/repo.git"), "https://github.com/user/repo.git");
+    assert_eq!(https_url("github.com/user/repo"), "https://github.com/user/repo");
+}
+
+#[test]
+fn test_build_digest() {
+    println!("The build digest should change with the source and build env, but not with the values of the secrets");
+    let droid = |env: &str, build_env: &str| -> Droid {
+        rocket::serde::json::serde_json::from_str(&format!(r#"{{"app_id": 1, "repo": "github.com/user/app", "branch": "main",
+            "env": [{}], "secrets": ["TOKEN"], "build_env": [{}]}}"#, env, build_env)).unwrap()
+    };
+    let builder = Builder::default();
+    let digest = build_digest("3f2a9c1", &builder, &droid(r#""TOKEN=a", "FOO=1""#, ""));
+    assert_eq!(digest, build_digest("3f2a9c1", &builder, &droid(r#""FOO=1", "TOKEN=b""#, "")));
+    assert_ne!(digest, build_digest("0123456", &builder, &droid(r#""TOKEN=a", "FOO=1""#, "")));
+    assert_ne!(digest, build_digest("3f2a9c1", &builder, &droid(r#""TOKEN=a", "FOO=2""#, "")));
+    assert_ne!(digest, build_digest("3f2a9c1", &builder, &droid(r#""TOKEN=a", "FOO=1""#, r#""NODE_ENV=production""#)));
+}
@@ -0,0 +1,163 @@
+use std::io;
+use std::time::{Duration, Instant};
+use app_address::AppAddress;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use crate::models::droid_state::DroidStatus;
+use crate::utility::docker::DockerClient;
+use crate::utility::nginx::Nginx;
+use crate::utility::snooze;
+use crate::utility::store::DroidStore;
+
+/// Largest request head the proxy accepts
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Served when a snoozed droid did not wake up in time, the page reloads itself until the droid answers
+const LOADING_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta http-equiv="refresh" content="3"><title>Waking up...</title></head>
+<body><p>This app is waking up, the page will reload in a few seconds.</p></body>
+</html>
+"#;
+
+/// Local reverse proxy, an alternative to nginx that can wake snoozed droids. Requests for an `AppAddress` of this
+/// server are forwarded to `<uid>:<port>` on the droid network, byte for byte,
+/// so that WebSocket upgrades go through untouched.
+#[derive(Clone)]
+pub struct Proxy {
+    pub store: DroidStore,
+    pub docker: DockerClient,
+    pub nginx: Nginx,
+    pub server_id: String,
+    pub domains: Vec<String>,
+    /// How long a request to a snoozed droid is held while the droid wakes up
+    pub wake_timeout: Duration,
+    /// Host the droids are reached at, their uid (the container name on the droid network) if None
+    pub upstream_host: Option<String>,
+}
+
+/// Parses the address of a request, if it is one of this server and domains
+pub fn route(host: &str, server_id: &str, domains: &[String]) -> Option<AppAddress> {
+    let address = host.parse::<AppAddress>().ok()?;
+    let local = address.server_id == server_id && domains.iter().any(|d| d.eq_ignore_ascii_case(&address.domain));
+    local.then_some(address)
+}
+
+/// Reads the request head, returns everything read so far and the length of the head
+async fn read_head(stream: &mut TcpStream) -> io::Result<(Vec<u8>, usize)> {
+    let mut buffered = Vec::new();
+    let mut buf = [0; 8192];
+    loop {
+        if let Some(end) = buffered.windows(4).position(|w| w == b"\r\n\r\n") {
+            return Ok((buffered, end + 4));
+        }
+        if buffered.len() > MAX_HEAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Request head too large"));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed before the request head"));
+        }
+        buffered.extend_from_slice(&buf[..n]);
+    }
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nRetry-After: 5\r\nConnection: close\r\n\r\n{}",
+                           status, content_type, body.len(), body);
+    stream.write_all(response.as_bytes()).await
+}
+
+impl Proxy {
+    /// Accepts connections on `listener` forever
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let proxy = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = proxy.handle(stream).await {
+                            println!("Proxy error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => println!("Proxy could not accept a connection: {}", e)
+            }
+        }
+    }
+
+    /// Connects to the droid, retrying until it answers or the wake timeout expires
+    async fn wait_ready(&self, addr: &str) -> Option<TcpStream> {
+        let deadline = Instant::now() + self.wake_timeout;
+        loop {
+            if let Ok(stream) = TcpStream::connect(addr).await {
+                return Some(stream);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    /// Routes one client connection. The connection sticks to the droid of its first request.
+    async fn handle(&self, mut client: TcpStream) -> io::Result<()> {
+        let (buffered, head_len) = read_head(&mut client).await?;
+        let head = String::from_utf8_lossy(&buffered[..head_len]).to_string();
+        let address = header(&head, "host").and_then(|host| route(host, &self.server_id, &self.domains));
+        // like the nginx routes, only the published port of the droid is reachable
+        let state = address.as_ref().and_then(|address| self.store.find_by_uid(&address.uid)
+            .filter(|state| state.port == Some(address.port)));
+        let (address, state) = match (address, state) {
+            (Some(address), Some(state)) => (address, state),
+            _ => return respond(&mut client, "404 Not Found", "text/plain", "App not found\n").await
+        };
+        let addr = match &self.upstream_host {
+            Some(host) => format!("{}:{}", host, address.port),
+            None => address.upstream(),
+        };
+
+        let upstream = match (state.status, &state.container) {
+            (DroidStatus::Running, _) => TcpStream::connect(&addr).await.ok(),
+            // like the nginx routes, a droid being rebuilt is served by its previous container
+            (DroidStatus::Building | DroidStatus::Built, Some(_)) => TcpStream::connect(&addr).await.ok(),
+            (DroidStatus::Snoozed, Some(container)) => {
+                if let Err(e) = snooze::wake(&self.store, &self.docker, &self.nginx, state.app_id, container).await {
+                    println!("Error waking droid {}: {}", state.app_id, e);
+                    return respond(&mut client, "502 Bad Gateway", "text/plain", "App could not be woken up\n").await;
+                }
+                match self.wait_ready(&addr).await {
+                    Some(upstream) => Some(upstream),
+                    None => return respond(&mut client, "503 Service Unavailable", "text/html", LOADING_PAGE).await
+                }
+            }
+            _ => return respond(&mut client, "503 Service Unavailable", "text/plain", "App is not running\n").await
+        };
+        let mut upstream = match upstream {
+            Some(upstream) => upstream,
+            None => return respond(&mut client, "502 Bad Gateway", "text/plain", "App is not answering\n").await
+        };
+
+        upstream.write_all(&buffered).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_route() {
+    println!("Addresses of this server should be routed, any other host should be rejected");
+    let domains = vec!["appoxy.com".to_string(), "localhost".to_string()];
+    assert_eq!(route("7d6g824.7000.ds1.appoxy.com", "ds1", &domains).map(|a| a.upstream()), Some("7d6g824:7000".to_string()));
+    assert_eq!(route("7D6G824.7000.DS1.localhost:8080", "ds1", &domains).map(|a| a.upstream()), Some("7d6g824:7000".to_string()));
+    assert_eq!(route("7d6g824.7000.ds2.appoxy.com", "ds1", &domains), None);
+    assert_eq!(route("7d6g824.7000.ds1.example.com", "ds1", &domains), None);
+    assert_eq!(route("myapp.appoxy.com", "ds1", &domains), None);
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+use std::time::Duration;
+use rocket::serde::Serialize;
+use command_runner::{CommandRunner, CommandSpec};
+use crate::models::droid_state::{DroidState, DroidStatus};
+use crate::models::git_credentials::GIT_CREDENTIALS;
+use crate::utility::docker::{ContainerSummary, DockerClient};
+use crate::utility::pipeline::{ActiveBuilds, SOURCES_DIR};
+use crate::utility::store::DroidStore;
+
+/// What the droid-server actually looks like, as opposed to the recorded droid states.
+#[derive(Debug, Default)]
+pub struct Observed {
+    pub containers: Vec<ContainerSummary>,
+    /// Local images as "<repository>:<tag>"
+    pub images: Vec<String>,
+    /// Names of the subdirectories of the dumps directory
+    pub dumps: Vec<String>,
+    /// App ids of the droids being built by this DSI process
+    pub active_builds: Vec<i64>,
+    /// Recorded build PIDs that still belong to a live `pack` process
+    pub live_builds: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// The build was interrupted, its process (if still alive) is killed and the droid is marked as failed
+    FailBuild { app_id: i64, pid: Option<u32>, reason: String },
+    /// The droid should be running but its container is not
+    StartContainer { app_id: i64, container: String },
+    /// The droid should be running but its container is gone
+    MarkFailed { app_id: i64, reason: String },
+    RemoveContainer { name: String },
+    RemoveImage { image: String },
+    RemoveDump { dir: String },
+}
+
+impl Action {
+    pub fn is_orphan(&self) -> bool {
+        matches!(self, Action::RemoveContainer { .. } | Action::RemoveImage { .. } | Action::RemoveDump { .. })
+    }
+}
+
+/// Compares the recorded states with the observed server and returns the actions needed to bring them back in sync.
+/// Builds not run by this DSI process were interrupted, i.e. by a restart, as builds do not outlive it.
+pub fn plan(states: &[DroidState], observed: &Observed) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let known = |app_id: i64| states.iter().any(|s| s.app_id == app_id);
+
+    for state in states {
+        match state.status {
+            DroidStatus::Building | DroidStatus::Built if !observed.active_builds.contains(&state.app_id) => {
+                let alive = state.build_pid.is_some_and(|pid| observed.live_builds.contains(&pid));
+                actions.push(Action::FailBuild {
+                    app_id: state.app_id,
+                    pid: state.build_pid.filter(|_| alive),
+                    reason: "Build was interrupted".to_string(),
+                });
+            }
+            DroidStatus::Running => {
+                let container = match &state.container {
+                    Some(container) => container,
+                    None => {
+                        actions.push(Action::MarkFailed { app_id: state.app_id, reason: "Droid has no container".to_string() });
+                        continue;
+                    }
+                };
+                match observed.containers.iter().find(|c| &c.name() == container) {
+                    Some(c) if c.state == "running" => {}
+                    Some(_) => actions.push(Action::StartContainer { app_id: state.app_id, container: container.clone() }),
+                    None => actions.push(Action::MarkFailed {
+                        app_id: state.app_id,
+                        reason: format!("Container {} is missing", container),
+                    }),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for container in &observed.containers {
+        if !container.app_id().is_some_and(known) {
+            actions.push(Action::RemoveContainer { name: container.name() });
+        }
+    }
+
+    // builder images are named "<app_id>:<stack>"
+    for image in &observed.images {
+        let repository = image.split(':').next().unwrap_or_default();
+        if let Ok(app_id) = repository.parse::<i64>() {
+            if !known(app_id) {
+                actions.push(Action::RemoveImage { image: image.clone() });
+            }
+        }
+    }
+
+    for dir in &observed.dumps {
+        if dir != SOURCES_DIR && !dir.parse::<i64>().is_ok_and(known) {
+            actions.push(Action::RemoveDump { dir: dir.clone() });
+        }
+    }
+
+    actions
+}
+
+fn is_live_build(pid: u32) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/comm", pid)) {
+        Ok(comm) => comm.trim() == "pack",
+        Err(_) => false
+    }
+}
+
+pub async fn observe(store: &DroidStore, docker: &DockerClient, builds: &ActiveBuilds) -> Result<Observed, String> {
+    let mut dumps = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(store.dumps_dir()) {
+        for entry in entries.flatten() {
+            // the git credentials of a droid may be set before its first deploy
+            if entry.path().is_dir() && !entry.path().join(GIT_CREDENTIALS).is_file() {
+                dumps.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+
+    Ok(Observed {
+        containers: docker.list_containers().await?,
+        images: docker.list_images().await?.into_iter().flat_map(|i| i.repo_tags).collect(),
+        dumps,
+        active_builds: builds.all(),
+        live_builds: store.all().iter().filter_map(|s| s.build_pid).filter(|pid| is_live_build(*pid)).collect(),
+    })
+}
+
+/// Applies the actions. Orphans are only removed if `remove_orphans` is set, otherwise they are reported.
+pub async fn apply(store: &DroidStore, docker: &DockerClient, runner: &dyn CommandRunner, actions: &[Action], remove_orphans: bool) {
+    for action in actions {
+        if action.is_orphan() && !remove_orphans {
+            println!("Reconciler found orphan: {:?}", action);
+            continue;
+        }
+        println!("Reconciler applying: {:?}", action);
+
+        let result = match action {
+            Action::FailBuild { app_id, pid, reason } => {
+                if let Some(pid) = pid {
+                    if let Err(e) = command_runner::output(runner, &CommandSpec::new("kill").arg(pid)).await {
+                        println!("Error killing build process {}: {}", pid, e);
+                    }
+                }
+                store.update(*app_id, |s| s.fail("build_interrupted", reason));
+                Ok(())
+            }
+            Action::StartContainer { app_id, container } => {
+                let result = docker.start_container(container).await.map_err(String::from);
+                if let Err(e) = &result {
+                    store.update(*app_id, |s| s.fail("container_start_failed", e));
+                }
+                result
+            }
+            Action::MarkFailed { app_id, reason } => {
+                store.update(*app_id, |s| s.fail("container_missing", reason));
+                Ok(())
+            }
+            Action::RemoveContainer { name } => docker.remove_container(name).await.map_err(String::from),
+            Action::RemoveImage { image } => docker.remove_image(image).await.map_err(String::from),
+            Action::RemoveDump { dir } => std::fs::remove_dir_all(format!("{}/{}", store.dumps_dir(), dir))
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            println!("Reconciler error: {}", e);
+        }
+    }
+}
+
+/// Reconciles once on boot, then every `interval` seconds (never if `interval` is 0).
+pub async fn run(store: DroidStore, docker: DockerClient, runner: Arc<dyn CommandRunner>, builds: ActiveBuilds, interval: u64,
+                 remove_orphans: bool) {
+    loop {
+        match observe(&store, &docker, &builds).await {
+            Ok(observed) => {
+                let actions = plan(&store.all(), &observed);
+                apply(&store, &docker, runner.as_ref(), &actions, remove_orphans).await;
+            }
+            Err(e) => println!("Reconciler could not observe the server: {}", e)
+        }
+
+        if interval == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+#[test]
+fn test_plan() {
+    println!("Interrupted builds should fail, stopped droids should restart and unknown resources should be orphans");
+    let mut building = DroidState::new(1, "droid1");
+    building.build_pid = Some(42);
+    // between two steps, the build pid is the one of a command that exited
+    let mut cloning = DroidState::new(5, "droid5");
+    cloning.build_pid = Some(43);
+    // built by a previous DSI process, which stopped before deploying it
+    let mut built = DroidState::new(6, "droid6");
+    built.status = DroidStatus::Built;
+    let mut running = DroidState::new(2, "droid2");
+    running.status = DroidStatus::Running;
+    running.container = Some("2".to_string());
+    let container = |name: &str, state: &str| ContainerSummary {
+        names: vec![format!("/{}", name)],
+        state: state.to_string(),
+        labels: [(crate::utility::docker::DROID_LABEL.to_string(), name.to_string())].into(),
+        ..Default::default()
+    };
+    let observed = Observed {
+        containers: vec![container("2", "exited"), container("3", "running")],
+        images: vec!["1:heroku-20".to_string(), "3:heroku-20".to_string(), "heroku/builder:22".to_string()],
+        dumps: vec!["1".to_string(), "2".to_string(), "4".to_string(), "5".to_string(), "6".to_string(), "sources".to_string()],
+        active_builds: vec![1, 5, 6],
+        live_builds: vec![42],
+    };
+
+    let states = vec![building, running, cloning, built];
+    assert_eq!(plan(&states, &observed), vec![
+        Action::StartContainer { app_id: 2, container: "2".to_string() },
+        Action::RemoveContainer { name: "3".to_string() },
+        Action::RemoveImage { image: "3:heroku-20".to_string() },
+        Action::RemoveDump { dir: "4".to_string() },
+    ]);
+
+    println!("Builds left Building or Built by a previous DSI process should fail, their pack process being killed if still alive");
+    let observed = Observed { active_builds: vec![], ..observed };
+    let actions = plan(&states, &observed);
+    assert_eq!(actions[0], Action::FailBuild { app_id: 1, pid: Some(42), reason: "Build was interrupted".to_string() });
+    assert_eq!(actions[2], Action::FailBuild { app_id: 5, pid: None, reason: "Build was interrupted".to_string() });
+    assert_eq!(actions[3], Action::FailBuild { app_id: 6, pid: None, reason: "Build was interrupted".to_string() });
+}
@@ -0,0 +1,67 @@
+use rocket::serde::json::Value;
+use rocket::serde::json::serde_json::json;
+use crate::config::DsiConfig;
+use crate::models::droid_state::DroidState;
+
+/// Reports the droids created and deleted on this server to the global router, so that the global proxy
+/// can route `<name>.<public domain>` to them. Droids without a name are not reported.
+#[derive(Debug, Clone)]
+pub struct RouterClient {
+    /// Base url of the global router, nothing is reported if None
+    pub url: Option<String>,
+    pub server_id: String,
+    pub domains: Vec<String>,
+}
+
+impl RouterClient {
+    pub fn new(config: &DsiConfig) -> RouterClient {
+        RouterClient { url: config.router_url.clone(), server_id: config.server_id.clone(), domains: config.domains.clone() }
+    }
+
+    /// The route event of the droid, announcing its first address
+    pub fn event(&self, kind: &str, state: &DroidState) -> Option<Value> {
+        let name = state.name.as_ref()?;
+        let address = state.addresses(&self.server_id, &self.domains).into_iter().next()?;
+        Some(json!({
+            "event": kind,
+            "app": name,
+            "address": address.to_string()
+        }))
+    }
+
+    /// Sends a "created", "moved" or "deleted" event for the droid. Failures are only logged, the router
+    /// catches up on the next event of the droid.
+    pub async fn report(&self, kind: &str, state: &DroidState) {
+        let (url, event) = match (&self.url, self.event(kind, state)) {
+            (Some(url), Some(event)) => (url, event),
+            _ => return
+        };
+        let result = reqwest::Client::new()
+            .post(format!("{}/events", url.trim_end_matches('/')))
+            .header("Content-Type", "application/json")
+            .body(event.to_string())
+            .send().await;
+        match result {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => println!("Global router rejected the {} event of droid {} ({})", kind, state.app_id, response.status()),
+            Err(e) => println!("Error reporting droid {} to the global router: {}", state.app_id, e)
+        }
+    }
+}
+
+#[test]
+fn test_event() {
+    println!("Named droids with a port should be announced at their first address");
+    let config = DsiConfig { domains: vec!["appoxy.com".to_string(), "localhost".to_string()], ..Default::default() };
+    let router = RouterClient::new(&config);
+    let mut state = DroidState::new(1, "7d6g824");
+    state.port = Some(7000);
+    assert_eq!(router.event("created", &state), None);
+
+    state.name = Some("myapp".to_string());
+    assert_eq!(router.event("created", &state), Some(json!({
+        "event": "created",
+        "app": "myapp",
+        "address": "7d6g824.7000.ds1.appoxy.com"
+    })));
+}
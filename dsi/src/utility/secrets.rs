@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use crate::models::env::EnvVars;
+
+/// Shown instead of the value of a secret
+pub const REDACTED: &str = "********";
+/// Prefix of the encrypted values, followed by the base64 of the nonce and ciphertext
+const PREFIX: &str = "enc:v1:";
+
+/// Encrypts the secret environment variables of the droids with the server key (AES-256-GCM), so that they are never
+/// written in plain text to the dumps directory.
+#[derive(Clone)]
+pub struct SecretBox {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for SecretBox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretBox { .. }")
+    }
+}
+
+impl SecretBox {
+    pub fn new(key: &[u8; 32]) -> SecretBox {
+        SecretBox { cipher: Aes256Gcm::new(key.into()) }
+    }
+
+    /// Reads the base64 encoded key from `path`. A new key is generated (readable by the owner only) if the file
+    /// does not exist.
+    pub fn load(path: &str) -> Result<SecretBox, String> {
+        let encoded = match std::fs::read_to_string(path) {
+            Ok(encoded) => encoded,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let encoded = base64::encode(rand::random::<[u8; 32]>());
+                if let Some(dir) = std::path::Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir).map_err(|e| format!("Error creating {}: {}", dir.display(), e))?;
+                }
+                let mut options = std::fs::OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                options.open(path)
+                    .and_then(|mut file| file.write_all(encoded.as_bytes()))
+                    .map_err(|e| format!("Error writing the secret key to {}: {}", path, e))?;
+                println!("Generated a new secret key at {}", path);
+                encoded
+            }
+            Err(e) => return Err(format!("Error reading the secret key from {}: {}", path, e))
+        };
+        let key: [u8; 32] = base64::decode(encoded.trim()).ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| format!("{} does not hold a base64 encoded 32 bytes key", path))?;
+        Ok(SecretBox::new(&key))
+    }
+
+    pub fn encrypt(&self, value: &str) -> String {
+        let nonce = rand::random::<[u8; 12]>();
+        let ciphertext = self.cipher.encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+            .expect("AES-GCM encryption does not fail on in-memory buffers");
+        format!("{}{}", PREFIX, base64::encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String, String> {
+        let sealed = value.strip_prefix(PREFIX).and_then(|sealed| base64::decode(sealed).ok())
+            .filter(|sealed| sealed.len() > 12)
+            .ok_or("Malformed encrypted value")?;
+        let plaintext = self.cipher.decrypt(Nonce::from_slice(&sealed[..12]), &sealed[12..])
+            .map_err(|_| "Secret cannot be decrypted with the server key")?;
+        String::from_utf8(plaintext).map_err(|e| e.to_string())
+    }
+
+    /// The environment with its secrets decrypted, for the container only
+    pub fn open(&self, env: &EnvVars, secrets: &BTreeSet<String>) -> Result<EnvVars, String> {
+        env.iter().map(|(key, value)| match secrets.contains(key) {
+            true => self.decrypt(value).map(|value| (key.clone(), value)).map_err(|e| format!("{}: {}", key, e)),
+            false => Ok((key.clone(), value.clone())),
+        }).collect()
+    }
+}
+
+/// The environment with the values of its secrets replaced by `REDACTED`
+pub fn redact(env: &EnvVars, secrets: &BTreeSet<String>) -> EnvVars {
+    env.iter().map(|(key, value)| match secrets.contains(key) {
+        true => (key.clone(), REDACTED.to_string()),
+        false => (key.clone(), value.clone()),
+    }).collect()
+}
+
+#[test]
+fn test_secret_box() {
+    println!("Secrets should only be readable with the key they were encrypted with");
+    let secrets = SecretBox::new(&[7; 32]);
+    let sealed = secrets.encrypt("hunter2");
+    assert!(sealed.starts_with(PREFIX) && !sealed.contains("hunter2"));
+    assert_ne!(sealed, secrets.encrypt("hunter2"));
+    assert_eq!(secrets.decrypt(&sealed), Ok("hunter2".to_string()));
+    assert!(SecretBox::new(&[8; 32]).decrypt(&sealed).is_err());
+    assert!(secrets.decrypt("hunter2").is_err());
+
+    let keys = BTreeSet::from(["DB_PASSWORD".to_string()]);
+    let env = EnvVars::from([("DB_PASSWORD".to_string(), sealed), ("PORT".to_string(), "8080".to_string())]);
+    assert_eq!(secrets.open(&env, &keys).unwrap()["DB_PASSWORD"], "hunter2");
+    assert_eq!(redact(&env, &keys)["DB_PASSWORD"], REDACTED);
+    assert_eq!(redact(&env, &keys)["PORT"], "8080");
+
+    println!("A missing key file should be generated, and read back");
+    let path = std::env::temp_dir().join(format!("dsi-secret-{}.key", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let generated = SecretBox::load(path.to_str().unwrap()).unwrap();
+    let sealed = generated.encrypt("hunter2");
+    assert_eq!(SecretBox::load(path.to_str().unwrap()).unwrap().decrypt(&sealed), Ok("hunter2".to_string()));
+    let _ = std::fs::remove_file(&path);
+}
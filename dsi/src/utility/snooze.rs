@@ -0,0 +1,28 @@
+use crate::models::droid_state::DroidStatus;
+use crate::utility::docker::DockerClient;
+use crate::utility::nginx::Nginx;
+use crate::utility::store::DroidStore;
+
+/// Re-renders the nginx config after the droid routes changed. Failures are only logged, nginx keeps the previous config.
+pub async fn reroute(nginx: &Nginx, store: &DroidStore) {
+    if let Err(err) = nginx.sync(store).await {
+        println!("Error updating nginx config: {}", err);
+    }
+}
+
+/// Stops the droid container, the droid keeps its route and is woken up by the next request.
+pub async fn snooze(store: &DroidStore, docker: &DockerClient, nginx: &Nginx, app_id: i64, container: &str) -> Result<(), String> {
+    docker.stop_container(container).await?;
+    store.update(app_id, |s| s.status = DroidStatus::Snoozed);
+    reroute(nginx, store).await;
+    Ok(())
+}
+
+/// Starts the container of a snoozed droid. The container may still be booting when this returns.
+pub async fn wake(store: &DroidStore, docker: &DockerClient, nginx: &Nginx, app_id: i64, container: &str) -> Result<(), String> {
+    println!("Waking droid {}", app_id);
+    docker.start_container(container).await?;
+    store.update(app_id, |s| s.status = DroidStatus::Running);
+    reroute(nginx, store).await;
+    Ok(())
+}
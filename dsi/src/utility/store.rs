@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use rand::Rng;
+use crate::models::droid_state::DroidState;
+
+/// In-memory map of the recorded droid states, backed by the state.toml files in the dumps directory.
+#[derive(Debug, Clone)]
+pub struct DroidStore {
+    dumps_dir: String,
+    droids: Arc<Mutex<HashMap<i64, DroidState>>>,
+}
+
+impl DroidStore {
+    /// Loads every `<dumps_dir>/<app_id>/state.toml` file. Directories without a readable state are skipped,
+    /// the reconciler reports them as orphans.
+    pub fn load(dumps_dir: &str) -> Result<DroidStore, Box<dyn std::error::Error>> {
+        let mut droids = HashMap::new();
+        if Path::new(dumps_dir).is_dir() {
+            for entry in std::fs::read_dir(dumps_dir)? {
+                let path = entry?.path().join("state.toml");
+                if !path.is_file() {
+                    continue;
+                }
+                match DroidState::load(&path) {
+                    Ok(mut state) => {
+                        if state.uid.is_empty() {
+                            state.uid = state.app_id.to_string();
+                        }
+                        droids.insert(state.app_id, state);
+                    }
+                    Err(e) => println!("Skipping unreadable droid state {:?}: {}", path, e)
+                }
+            }
+        }
+
+        Ok(DroidStore {
+            dumps_dir: dumps_dir.to_string(),
+            droids: Arc::new(Mutex::new(droids)),
+        })
+    }
+
+    pub fn dumps_dir(&self) -> &str {
+        &self.dumps_dir
+    }
+
+    pub fn get(&self, app_id: i64) -> Option<DroidState> {
+        self.droids.lock().unwrap().get(&app_id).cloned()
+    }
+
+    pub fn find_by_uid(&self, uid: &str) -> Option<DroidState> {
+        self.droids.lock().unwrap().values().find(|s| s.uid == uid).cloned()
+    }
+
+    /// Generates a uid that no droid uses yet: 7 lowercase letters and digits, never only digits so that it cannot
+    /// be mistaken for an app id.
+    pub fn new_uid(&self) -> String {
+        const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+        let droids = self.droids.lock().unwrap();
+        let mut rng = rand::thread_rng();
+        loop {
+            let uid: String = (0..7).map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char).collect();
+            if uid.bytes().any(|b| b.is_ascii_lowercase()) && !droids.values().any(|s| s.uid == uid) {
+                return uid;
+            }
+        }
+    }
+
+    pub fn all(&self) -> Vec<DroidState> {
+        let mut states: Vec<DroidState> = self.droids.lock().unwrap().values().cloned().collect();
+        states.sort_by_key(|s| s.app_id);
+        states
+    }
+
+    /// Records the state in memory and dumps it to disk
+    pub fn save(&self, state: DroidState) -> Result<(), Box<dyn std::error::Error>> {
+        let mut droids = self.droids.lock().unwrap();
+        state.save(&self.dumps_dir)?;
+        droids.insert(state.app_id, state);
+        Ok(())
+    }
+
+    /// Applies `f` to the recorded state of `app_id` and saves the result. Returns false if the droid is unknown.
+    /// The store is locked until the state is saved, so that concurrent updates do not overwrite each other.
+    pub fn update<F: FnOnce(&mut DroidState)>(&self, app_id: i64, f: F) -> bool {
+        let mut droids = self.droids.lock().unwrap();
+        let state = match droids.get_mut(&app_id) {
+            Some(state) => state,
+            None => return false
+        };
+        f(state);
+        if let Err(e) = state.save(&self.dumps_dir) {
+            println!("Error saving state of droid {}: {}", app_id, e);
+        }
+        true
+    }
+
+    /// Forgets the droid and removes its dumps (state, builder config, sources). Returns false if the droid is unknown.
+    pub fn remove(&self, app_id: i64) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.droids.lock().unwrap().remove(&app_id).is_none() {
+            return Ok(false);
+        }
+        let dir = format!("{}/{}", self.dumps_dir, app_id);
+        if Path::new(&dir).exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        Ok(true)
+    }
+}
+
+#[test]
+fn test_uids() {
+    println!("Generated uids should be short DNS labels, unique, and states dumped without a uid should get their app id");
+    let dir = std::env::temp_dir().join(format!("dsi-store-uids-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("9")).unwrap();
+    std::fs::write(dir.join("9/state.toml"), "app_id = 9\nstatus = \"running\"\n").unwrap();
+    let store = DroidStore::load(dir.to_str().unwrap()).unwrap();
+    assert_eq!(store.get(9).unwrap().uid, "9");
+
+    let mut uids = std::collections::HashSet::new();
+    for app_id in 0..100 {
+        let uid = store.new_uid();
+        assert!(app_address::validate_uid(&uid).is_ok());
+        assert_eq!(uid.len(), 7);
+        assert!(uid.parse::<i64>().is_err());
+        store.save(DroidState::new(app_id, &uid)).unwrap();
+        uids.insert(uid);
+    }
+    assert_eq!(uids.len(), 100);
+    assert_eq!(store.find_by_uid(store.get(42).unwrap().uid.as_str()).map(|s| s.app_id), Some(42));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_concurrent_updates() {
+    println!("Concurrent updates of a droid should all be recorded, in memory and on disk");
+    let dir = std::env::temp_dir().join(format!("dsi-store-updates-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = DroidStore::load(dir.to_str().unwrap()).unwrap();
+    let mut state = DroidState::new(1, "droid1");
+    state.port = Some(0);
+    store.save(state).unwrap();
+    let threads: Vec<_> = (0..8).map(|_| {
+        let store = store.clone();
+        std::thread::spawn(move || for _ in 0..50 {
+            store.update(1, |s| s.port = s.port.map(|port| port + 1));
+        })
+    }).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(store.get(1).unwrap().port, Some(400));
+    assert_eq!(DroidState::load(&dir.join("1/state.toml")).unwrap().port, Some(400));
+    let _ = std::fs::remove_dir_all(&dir);
+}
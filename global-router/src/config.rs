@@ -0,0 +1,28 @@
+use rocket::serde::{Deserialize, Serialize};
+
+/// Router settings, extracted from Rocket's figment (Rocket.toml or `ROCKET_*` environment variables).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(default)]
+pub struct RouterConfig {
+    /// File the route table is persisted to
+    pub table_path: String,
+    /// Public domains of the apps, `myapp` is served at `myapp.<domain>` for each of them
+    pub public_domains: Vec<String>,
+    /// Push mode: the nginx `map` config is rendered to this file whenever the table changes. Unset, proxies have to
+    /// look the routes up or pull the map from `GET /map`.
+    pub nginx_map: Option<String>,
+    /// nginx binary used to validate the map and reload the global proxy
+    pub nginx: String,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        RouterConfig {
+            table_path: "./routes.toml".to_string(),
+            public_domains: vec!["appoxy.com".to_string()],
+            nginx_map: None,
+            nginx: "nginx".to_string(),
+        }
+    }
+}
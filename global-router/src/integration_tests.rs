@@ -0,0 +1,58 @@
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::Client;
+use rocket::serde::json::{serde_json, Value};
+
+#[rocket::async_test]
+async fn route_lifecycle() {
+    println!("Reported droids should be resolvable by app name, and cached lookups should be invalidated when they move");
+
+    let dir = std::env::temp_dir().join(format!("global-router-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let map_path = dir.join("droids.map");
+    let figment = rocket::Config::figment()
+        .merge(("table_path", dir.join("routes.toml").to_str().unwrap()))
+        .merge(("nginx_map", map_path.to_str().unwrap()))
+        .merge(("nginx", "true"))
+        .merge(("public_domains", vec!["appoxy.com"]));
+    let client = Client::tracked(crate::router(rocket::custom(figment))).await.expect("valid rocket instance");
+
+    let response = client.post("/events")
+        .body(r#"{"event": "created", "app": "myapp", "address": "7d6g824.7000.ds1.appoxy.com"}"#)
+        .dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(std::fs::read_to_string(&map_path).unwrap().contains("myapp.appoxy.com 7d6g824.7000.ds1.appoxy.com;"));
+
+    let response = client.get("/routes/myapp").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let etag = response.headers().get_one("ETag").unwrap().to_string();
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["uid"], "7d6g824");
+    assert_eq!(body["data"]["port"], 7000);
+    assert_eq!(body["data"]["server_id"], "ds1");
+
+    println!("A lookup revalidated with an unchanged route should not be sent again");
+    let response = client.get("/routes/myapp").header(Header::new("If-None-Match", etag.clone())).dispatch().await;
+    assert_eq!(response.status(), Status::NotModified);
+
+    client.post("/events")
+        .body(r#"{"event": "moved", "app": "myapp", "address": "7d6g824.7000.ds2.appoxy.com"}"#)
+        .dispatch().await;
+    let response = client.get("/routes/myapp").header(Header::new("If-None-Match", etag)).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["address"], "7d6g824.7000.ds2.appoxy.com");
+    assert!(client.get("/map").dispatch().await.into_string().await.unwrap().contains("myapp.appoxy.com 7d6g824.7000.ds2.appoxy.com;"));
+
+    println!("Invalid events should be rejected and deleted apps should be unknown");
+    let response = client.post("/events")
+        .body(r#"{"event": "created", "app": "myapp", "address": "myapp.appoxy.com"}"#)
+        .dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+    client.post("/events")
+        .body(r#"{"event": "deleted", "app": "myapp", "address": "7d6g824.7000.ds2.appoxy.com"}"#)
+        .dispatch().await;
+    assert_eq!(client.get("/routes/myapp").dispatch().await.status(), Status::NotFound);
+    assert!(!std::fs::read_to_string(&map_path).unwrap().contains("myapp"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,44 @@
+#[macro_use] extern crate rocket;
+use std::sync::Arc;
+use rocket::{Build, Rocket};
+use rocket::fairing::AdHoc;
+use command_runner::TokioRunner;
+use crate::config::RouterConfig;
+use crate::nginx::MapPusher;
+use crate::table::RouteTable;
+
+#[cfg(test)] mod integration_tests;
+mod config;
+mod nginx;
+mod routes;
+mod table;
+
+/// Attaches the route table, the map pusher and the routes to `rocket`.
+fn router(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket
+        .attach(AdHoc::config::<RouterConfig>())
+        .attach(AdHoc::try_on_ignite("Route Table", |rocket| async {
+            let config = rocket.state::<RouterConfig>().unwrap().clone();
+            match RouteTable::load(&config.table_path) {
+                Ok(table) => Ok(rocket
+                    .manage(table)
+                    .manage(MapPusher::new(config.nginx_map.clone(), &config.nginx, Arc::new(TokioRunner)))),
+                Err(e) => {
+                    println!("Error loading the route table from {}: {}", config.table_path, e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .mount("/routes", routes![routes::lookup, routes::list])
+        .mount("/events", routes![routes::events])
+        .mount("/map", routes![routes::map])
+}
+
+#[rocket::main]
+async fn main() -> Result<(), Box<rocket::Error>> {
+    let _rocket = router(rocket::build())
+        .launch()
+        .await?;
+
+    Ok(())
+}
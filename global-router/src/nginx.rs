@@ -0,0 +1,38 @@
+use std::sync::Arc;
+use command_runner::CommandRunner;
+use command_runner::nginx::NginxConfig;
+
+/// Push mode: writes the nginx map of the global proxy and reloads it.
+#[derive(Clone)]
+pub struct MapPusher {
+    /// File the map is written to, nothing is pushed if None
+    pub map: Option<NginxConfig>,
+}
+
+impl MapPusher {
+    pub fn new(path: Option<String>, nginx: &str, runner: Arc<dyn CommandRunner>) -> MapPusher {
+        MapPusher { map: path.map(|path| NginxConfig::new(&path, nginx, runner)) }
+    }
+
+    /// Writes the map returned by `render`, validates it with `nginx -t` and reloads nginx. An invalid map is rolled back.
+    pub async fn push(&self, render: impl FnOnce() -> String) -> Result<(), String> {
+        match &self.map {
+            Some(map) => map.sync(render).await,
+            None => Ok(())
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn test_push_rollback() {
+    println!("A map failing `nginx -t` should be rolled back");
+    let path = std::env::temp_dir().join(format!("global-router-rollback-{}.map", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    std::fs::write(&path, "# previous map\n").unwrap();
+    let pusher = MapPusher::new(Some(path.clone()), "false", Arc::new(command_runner::TokioRunner));
+
+    let result = pusher.push(|| "# new map\n".to_string()).await;
+    assert_eq!(result, Err("false -t exited with code 1: ".to_string()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "# previous map\n");
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,114 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, status, Responder, Response};
+use rocket::serde::json::{Json, Value};
+use rocket::serde::json::serde_json::json;
+use rocket::State;
+use crate::config::RouterConfig;
+use crate::nginx::MapPusher;
+use crate::table::{RouteEvent, RouteTable};
+
+/// The `If-None-Match` header of a proxy revalidating a cached lookup
+pub struct IfNoneMatch(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match").map(|etag| etag.trim().to_string())))
+    }
+}
+
+/// A route lookup. Proxies may cache it, but have to revalidate it with its ETag since routes change
+/// whenever a DSI reports an event.
+pub enum Lookup {
+    Found { etag: String, body: Value },
+    NotModified { etag: String },
+}
+
+impl<'r> Responder<'r, 'static> for Lookup {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            Lookup::Found { etag, body } => Response::build_from(Json(body).respond_to(request)?)
+                .raw_header("ETag", etag)
+                .raw_header("Cache-Control", "no-cache")
+                .ok(),
+            Lookup::NotModified { etag } => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+        }
+    }
+}
+
+/// Resolves an app name to the address of its droid
+#[get("/<app>")]
+pub fn lookup(app: &str, if_none_match: IfNoneMatch, table: &State<RouteTable>) -> Result<Lookup, status::Custom<Value>> {
+    let route = match table.get(app) {
+        Some(route) => route,
+        None => return Err(status::Custom(Status::NotFound, json!({
+            "message": "App not found",
+            "data": {}
+        })))
+    };
+    let etag = format!("\"{}\"", route.version);
+    if if_none_match.0.as_deref() == Some(etag.as_str()) {
+        return Ok(Lookup::NotModified { etag });
+    }
+    let address = route.address.parse::<app_address::AppAddress>().ok();
+    Ok(Lookup::Found {
+        etag,
+        body: json!({
+            "message": "App route",
+            "data": {
+                "app": app,
+                "address": route.address,
+                "uid": address.as_ref().map(|a| a.uid.clone()),
+                "port": address.as_ref().map(|a| a.port),
+                "server_id": address.as_ref().map(|a| a.server_id.clone()),
+            }
+        }),
+    })
+}
+
+#[get("/")]
+pub fn list(table: &State<RouteTable>) -> Value {
+    json!({
+        "message": "App routes",
+        "data": {
+            "routes": table.all()
+        }
+    })
+}
+
+/// Records a droid creation, move or deletion reported by a DSI. In push mode, the global proxy is updated
+/// before responding.
+#[post("/", data = "<event>")]
+pub async fn events(event: Json<RouteEvent>, table: &State<RouteTable>, pusher: &State<MapPusher>, config: &State<RouterConfig>) -> status::Custom<Value> {
+    let changed = match table.apply(&event) {
+        Ok(changed) => changed,
+        Err(err) => return status::Custom(Status::BadRequest, json!({
+            "message": "Invalid route event",
+            "error": err,
+            "data": {}
+        }))
+    };
+    if changed {
+        if let Err(err) = pusher.push(|| table.map(&config.public_domains)).await {
+            println!("Error pushing the nginx map: {}", err);
+        }
+    }
+    status::Custom(Status::Ok, json!({
+        "message": if changed { "Route updated" } else { "Route unchanged" },
+        "data": {
+            "changed": changed
+        }
+    }))
+}
+
+/// Pull mode: the nginx map of the current table
+#[get("/")]
+pub fn map(table: &State<RouteTable>, config: &State<RouterConfig>) -> String {
+    table.map(&config.public_domains)
+}
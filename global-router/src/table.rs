@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use app_address::{is_dns_label, AppAddress};
+use rocket::serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// A droid was deployed for the app
+    Created,
+    /// The droid of the app was redeployed somewhere else
+    Moved,
+    /// The droid of the app was deleted
+    Deleted,
+}
+
+/// What a DSI (or DAMS) reports about the droid of an app
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RouteEvent {
+    pub event: EventKind,
+    /// Public name of the app, i.e. "myapp"
+    pub app: String,
+    /// Address of the droid, i.e. "7d6g824.7000.ds1.appoxy.com"
+    pub address: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Route {
+    pub address: String,
+    /// Version of the table when the route last changed, used as its ETag
+    pub version: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Table {
+    /// Incremented on every change
+    version: u64,
+    routes: BTreeMap<String, Route>,
+    /// Rendered nginx map, dropped whenever the table changes
+    #[serde(skip)]
+    map: Option<String>,
+}
+
+/// The app name to `AppAddress` table, kept in memory and persisted to a TOML file.
+#[derive(Debug, Clone)]
+pub struct RouteTable {
+    path: String,
+    table: Arc<Mutex<Table>>,
+}
+
+/// Renders an nginx `map` from the public hosts of the apps to their droid addresses. The global proxy
+/// proxies to `$droid_address`, an empty address meaning that the app is unknown.
+pub fn render_map(routes: &BTreeMap<String, Route>, public_domains: &[String]) -> String {
+    let mut map = String::from("# Generated by the global router, changes will be overwritten.\nmap $host $droid_address {\n    hostnames;\n    default \"\";\n");
+    for (app, route) in routes {
+        for domain in public_domains {
+            map.push_str(&format!("    {}.{} {};\n", app, domain, route.address));
+        }
+    }
+    map.push_str("}\n");
+    map
+}
+
+impl RouteTable {
+    /// Loads the table from `path`, starting empty if the file does not exist yet
+    pub fn load(path: &str) -> Result<RouteTable, Box<dyn std::error::Error>> {
+        let table = match Path::new(path).is_file() {
+            true => toml::from_str(&std::fs::read_to_string(path)?)?,
+            false => Table::default(),
+        };
+        Ok(RouteTable { path: path.to_string(), table: Arc::new(Mutex::new(table)) })
+    }
+
+    pub fn get(&self, app: &str) -> Option<Route> {
+        self.table.lock().unwrap().routes.get(app).cloned()
+    }
+
+    pub fn all(&self) -> BTreeMap<String, Route> {
+        self.table.lock().unwrap().routes.clone()
+    }
+
+    /// The nginx map of the current table, rendered again only if the table changed since the last call
+    pub fn map(&self, public_domains: &[String]) -> String {
+        let mut table = self.table.lock().unwrap();
+        if table.map.is_none() {
+            table.map = Some(render_map(&table.routes, public_domains));
+        }
+        table.map.clone().unwrap_or_default()
+    }
+
+    /// Applies a reported event and persists the table. Returns whether the table changed.
+    /// A deletion only applies to the reported address, so that a late report from the previous server of a moved
+    /// app does not drop its new route.
+    pub fn apply(&self, event: &RouteEvent) -> Result<bool, String> {
+        if !is_dns_label(&event.app) {
+            return Err(format!("Invalid app name '{}'", event.app));
+        }
+        let address = event.address.parse::<AppAddress>().map_err(|e| e.to_string())?.to_string();
+
+        let mut table = self.table.lock().unwrap();
+        let version = table.version + 1;
+        let changed = match event.event {
+            EventKind::Created | EventKind::Moved => {
+                let current = table.routes.get(&event.app).map(|r| r.address.clone());
+                if current.as_ref() == Some(&address) {
+                    false
+                } else {
+                    if let Some(current) = current {
+                        println!("App {} moved from {} to {}", event.app, current, address);
+                    }
+                    table.routes.insert(event.app.clone(), Route { address, version });
+                    true
+                }
+            }
+            EventKind::Deleted => match table.routes.get(&event.app) {
+                Some(route) if route.address == address => {
+                    table.routes.remove(&event.app);
+                    true
+                }
+                _ => false,
+            },
+        };
+        if changed {
+            table.version = version;
+            table.map = None;
+            let dump = toml::to_string(&*table).map_err(|e| e.to_string())?;
+            std::fs::write(&self.path, dump).map_err(|e| format!("Error saving the route table to {}: {}", self.path, e))?;
+        }
+        Ok(changed)
+    }
+}
+
+#[test]
+fn test_apply() {
+    println!("Created and moved apps should be routed to their last address, stale deletions should be ignored");
+    let path = std::env::temp_dir().join(format!("global-router-table-{}.toml", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let table = RouteTable::load(path.to_str().unwrap()).unwrap();
+    let event = |event: EventKind, address: &str| RouteEvent { event, app: "myapp".to_string(), address: address.to_string() };
+
+    assert_eq!(table.apply(&event(EventKind::Created, "7d6g824.7000.ds1.appoxy.com")), Ok(true));
+    assert_eq!(table.apply(&event(EventKind::Created, "7d6g824.7000.ds1.appoxy.com")), Ok(false));
+    assert_eq!(table.apply(&event(EventKind::Moved, "7d6g824.7000.ds2.appoxy.com")), Ok(true));
+    assert_eq!(table.apply(&event(EventKind::Deleted, "7d6g824.7000.ds1.appoxy.com")), Ok(false));
+    assert_eq!(table.get("myapp").map(|r| (r.address, r.version)), Some(("7d6g824.7000.ds2.appoxy.com".to_string(), 2)));
+    assert!(table.apply(&event(EventKind::Created, "myapp.appoxy.com")).is_err());
+
+    println!("The table should survive a restart");
+    let reloaded = RouteTable::load(path.to_str().unwrap()).unwrap();
+    assert_eq!(reloaded.all(), table.all());
+
+    assert_eq!(table.apply(&event(EventKind::Deleted, "7d6g824.7000.ds2.appoxy.com")), Ok(true));
+    assert_eq!(table.get("myapp"), None);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_render_map() {
+    println!("Every public host of an app should map to its droid address");
+    let routes = BTreeMap::from([("myapp".to_string(), Route { address: "7d6g824.7000.ds1.appoxy.com".to_string(), version: 1 })]);
+    let map = render_map(&routes, &["appoxy.com".to_string(), "appoxy.dev".to_string()]);
+    assert!(map.contains("map $host $droid_address {"));
+    assert!(map.contains("    myapp.appoxy.com 7d6g824.7000.ds1.appoxy.com;\n"));
+    assert!(map.contains("    myapp.appoxy.dev 7d6g824.7000.ds1.appoxy.com;\n"));
+}
@@ -180,32 +180,14 @@ provision_nginx() {
   rm /etc/nginx/sites-enabled/default
   rm /etc/nginx/sites-available/default
 
-  verbose "${BLUE}Parsing domains...${NC}"
-  # Seperate domains by space
-  domains=$(echo "$DOMAIN" | tr -d '[:space:]' | tr ',' ' ')
-  server_names=""
-  for dom in $domains; do
-    server_names="$server_names ~^(?<app_id>.+)\.$dom$"
-  done
-  verbose "${BLUE}Parsed server_name: $server_names${NC}"
-
   verbose "${BLUE}Generating Nginx config file.${NC}"
-  # Create Nginx config file that redirects requests to app_id
-  tee /etc/nginx/sites-available/droid-server <<EOF
+  # The DSI renders this file from its droid states (routing <uid>.<port>.<ds-id>.<domain> to the droids) and reloads
+  # Nginx whenever droids change. Until then, every request gets a 404.
+  tee /etc/nginx/sites-available/droid-server <<"EOF"
 server {
-    listen 80;
-    server_name $server_names;
-
-    location / {
-EOF
-  tee -a /etc/nginx/sites-available/droid-server <<"EOF"
-        proxy_pass http://$app_id:80;
-        proxy_set_header Host $host;
-        proxy_set_header X-Real-IP $remote_addr;
-        proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
-        proxy_set_header X-Forwarded-Proto $scheme;
-
-        }
+    listen 80 default_server;
+    server_name _;
+    return 404;
 }
 EOF
 
@@ -260,6 +242,11 @@ provision_dsi() {
 
   # TODO: Provision DSI
 
+  # Domains the DSI routes the droids on, read by the DSI from its environment
+  mkdir -p /etc/appoxy
+  echo "ROCKET_DOMAINS=[$(echo "$DOMAIN" | tr -d '[:space:]')]" > /etc/appoxy/dsi.env
+  verbose "${BLUE}DSI environment written to /etc/appoxy/dsi.env${NC}"
+
   echo "${GREEN}DSI provisioning completed.${NC}"
 }
 